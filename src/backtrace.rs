@@ -0,0 +1,238 @@
+//! Best-effort backtraces, found by scanning the stack for return addresses.
+//!
+//! MIPS code doesn't keep frame pointers, so the stack can't be walked reliably without unwind
+//! information. Instead, every stack word is checked for something that looks like a return
+//! address: an address inside the module's text segment, just after a call instruction. This
+//! can include stale return addresses left over from earlier calls, and miss functions that never
+//! save `ra`, but is usually enough to see how a record was reached.
+
+use core::fmt::{self, Display};
+
+use crate::module::{current_text_segment, TextSegment};
+use crate::thread;
+
+/// Most return addresses kept in a [Backtrace].
+pub const MAX_FRAMES: usize = 16;
+
+/// Most bytes of the stack scanned when capturing a backtrace.
+const MAX_SCAN_LEN: usize = 16 * 1024;
+
+/// Return addresses found on the stack, innermost first.
+///
+/// Addresses are where the module was loaded, not where it was linked. The `psp-symbolize` tool
+/// in this repository turns them into function names.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Backtrace {
+    frames: [u32; MAX_FRAMES],
+    len: usize,
+}
+
+impl Backtrace {
+    /// Scan the current thread's stack for return addresses.
+    ///
+    /// The backtrace is empty if the stack or text segment can't be found, e.g. on a host.
+    pub fn capture() -> Self {
+        let mut backtrace = Backtrace {
+            frames: [0; MAX_FRAMES],
+            len: 0,
+        };
+
+        let (Some(stack), Some(text)) = (thread::current_stack(), current_text_segment()) else {
+            return backtrace;
+        };
+
+        // Everything above this local belongs to the callers.
+        let marker = 0u32;
+        let sp = core::ptr::addr_of!(marker) as u32;
+        if !stack.contains(&sp) {
+            return backtrace;
+        }
+
+        let len = (stack.end - sp).min(MAX_SCAN_LEN as u32) & !3;
+
+        // The range was checked against the thread's stack, and the text segment is mapped for as
+        // long as the module is loaded.
+        let (stack, code) = unsafe {
+            (
+                core::slice::from_raw_parts(sp as *const u8, len as usize),
+                core::slice::from_raw_parts(text.addr as *const u8, text.size as usize),
+            )
+        };
+
+        backtrace.len = scan_stack(stack, text, code, &mut backtrace.frames);
+        backtrace
+    }
+
+    /// The return addresses, innermost first.
+    pub fn frames(&self) -> &[u32] {
+        &self.frames[..self.len]
+    }
+
+    /// Whether no return addresses were found.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Writes `backtrace:` followed by each return address.
+impl Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "backtrace:")?;
+        for frame in self.frames() {
+            write!(f, " {:#010x}", frame)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Find the plausible return addresses in `stack`, storing them in `frames`.
+///
+/// `code` holds the contents of `text`, and is used to check that each address follows a call.
+/// Stops once `frames` is full. Returns the number of return addresses found.
+pub(crate) fn scan_stack(
+    stack: &[u8],
+    text: TextSegment,
+    code: &[u8],
+    frames: &mut [u32],
+) -> usize {
+    let mut len = 0;
+
+    for word in stack.chunks_exact(4) {
+        if len == frames.len() {
+            break;
+        }
+
+        let addr = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+        if is_return_address(addr, text, code) {
+            frames[len] = addr;
+            len += 1;
+        }
+    }
+
+    len
+}
+
+/// Whether `addr` is just after a call, i.e. the call is two instructions earlier, before its
+/// delay slot.
+fn is_return_address(addr: u32, text: TextSegment, code: &[u8]) -> bool {
+    if addr & 3 != 0 || !text.contains(addr) {
+        return false;
+    }
+
+    let Some(offset) = (addr - text.addr).checked_sub(8) else {
+        return false;
+    };
+    let Some(call) = code.get(offset as usize..offset as usize + 4) else {
+        return false;
+    };
+
+    is_call(u32::from_le_bytes([call[0], call[1], call[2], call[3]]))
+}
+
+/// Whether `instruction` links `ra`: `jal`, `jalr` or one of the `bltzal` family.
+fn is_call(instruction: u32) -> bool {
+    const SPECIAL: u32 = 0;
+    const REGIMM: u32 = 1;
+    const JAL: u32 = 3;
+    const JALR: u32 = 9;
+
+    match instruction >> 26 {
+        JAL => true,
+        SPECIAL => instruction & 0x3f == JALR,
+        // bltzal, bgezal, bltzall and bgezall.
+        REGIMM => (instruction >> 16) & 0x1c == 0x10,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+    use std::vec::Vec;
+
+    const TEXT: TextSegment = TextSegment {
+        addr: 0x0880_4000,
+        size: 0x20,
+    };
+
+    /// `jal`, its delay slot, `jalr $ra, $t9`, its delay slot, `bgezal`, its delay slot, then
+    /// two plain `addiu` instructions.
+    fn code() -> Vec<u8> {
+        [
+            0x0e20_1000u32,
+            0,
+            0x0320_f809,
+            0,
+            0x0411_0004,
+            0,
+            0x2484_0001,
+            0x2484_0001,
+        ]
+        .iter()
+        .flat_map(|instruction| instruction.to_le_bytes())
+        .collect()
+    }
+
+    fn stack(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    fn scan(words: &[u32], frames: &mut [u32]) -> Vec<u32> {
+        let len = scan_stack(&stack(words), TEXT, &code(), frames);
+        frames[..len].to_vec()
+    }
+
+    #[test]
+    fn return_addresses_follow_calls() {
+        let words = [
+            0x0880_4008, // After jal.
+            0x0000_0001,
+            0x0880_4010, // After jalr.
+            0x0880_4018, // After bgezal.
+            0x0880_401c, // After a nop.
+            0x0880_4020, // Outside the text segment.
+            0x0880_4004, // Too early for a call to precede it.
+            0x0880_400a, // Misaligned.
+            0x0880_4000,
+        ];
+
+        assert_eq!(
+            scan(&words, &mut [0; 9]),
+            [0x0880_4008, 0x0880_4010, 0x0880_4018]
+        );
+    }
+
+    #[test]
+    fn scan_stops_when_frames_are_full() {
+        let words = [0x0880_4008, 0x0880_4010, 0x0880_4018];
+
+        assert_eq!(scan(&words, &mut [0; 2]), [0x0880_4008, 0x0880_4010]);
+    }
+
+    #[test]
+    fn trailing_bytes_are_ignored() {
+        let mut bytes = stack(&[0x0880_4008]);
+        bytes.extend_from_slice(&[0x10, 0x40]);
+
+        let mut frames = [0; 4];
+        assert_eq!(scan_stack(&bytes, TEXT, &code(), &mut frames), 1);
+    }
+
+    #[test]
+    fn display() {
+        let mut backtrace = Backtrace {
+            frames: [0; MAX_FRAMES],
+            len: 2,
+        };
+        backtrace.frames[..2].copy_from_slice(&[0x0880_4008, 0x0880_5a10]);
+
+        assert_eq!(backtrace.to_string(), "backtrace: 0x08804008 0x08805a10");
+    }
+
+    #[test]
+    fn capture_is_empty_without_a_stack() {
+        assert!(Backtrace::capture().is_empty());
+    }
+}
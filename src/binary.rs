@@ -0,0 +1,785 @@
+//! Compact binary records, logged with [log_binary](crate::log_binary).
+//!
+//! Formatting a record takes time on the PSP, and the formatted text is usually much longer than
+//! the values in it. A binary record instead holds an id for its format string, followed by the
+//! raw bytes of its arguments. The format strings are kept in the [FORMAT_SECTION] ELF section
+//! and never written out. The `psp-logdecode` tool in this repository reads them from the
+//! unstripped ELF or PRX and turns the records back into text. All integers are little-endian.
+//!
+//! Each record is written to its sinks with [LogSink::write_binary] as a frame:
+//!
+//! | Size | Field |
+//! | --- | --- |
+//! | 1 | [FRAME_START] |
+//! | 2 | Length of the rest of the frame |
+//! | 4 | Id: the address the record's [FormatEntry] was loaded at |
+//! | n | Arguments, each a type byte followed by the value |
+//!
+//! [FRAME_START] never appears in UTF-8, so frames can be told apart from the lines written by
+//! other records to the same stream or file. Strings are written as a `u16` length followed by
+//! UTF-8, `bool`s as a byte and `char`s as a `u32`.
+//!
+//! A format entry in the ELF section is:
+//!
+//! | Size | Field |
+//! | --- | --- |
+//! | 1 | Level, from 1 for [Level::Error] to 5 for [Level::Trace] |
+//! | 2 + n | Target, as a `u16` length followed by UTF-8 |
+//! | 2 + n | Format string, as a `u16` length followed by UTF-8 |
+
+use core::fmt::{self, Display};
+use core::sync::atomic::Ordering;
+
+use log::{Level, Metadata};
+
+use crate::interrupt::{self, INTERRUPT_RECORDS};
+use crate::lock::RECORD_LOCK;
+use crate::{
+    reentrancy, write_to_sinks, LogSink, PspLoggerConfig, StdioSink, LOGGER_CONF, MAX_LINE_LEN,
+    NESTED_RECORDS_DROPPED,
+};
+
+/// Byte at the start of every frame.
+pub const FRAME_START: u8 = 0xff;
+
+/// Name of the ELF section holding the [FormatEntry] of every binary record.
+pub const FORMAT_SECTION: &str = "psp_logger_fmt";
+
+/// Maximum length in bytes of a frame.
+///
+/// Arguments that don't fit are left out, apart from strings, which are cut short.
+pub const MAX_FRAME_LEN: usize = MAX_LINE_LEN;
+
+/// Length of a frame before its arguments.
+const HEADER_LEN: usize = 7;
+
+/// The type bytes written before each argument.
+mod tag {
+    pub(super) const U8: u8 = 0x01;
+    pub(super) const U16: u8 = 0x02;
+    pub(super) const U32: u8 = 0x03;
+    pub(super) const U64: u8 = 0x04;
+    pub(super) const I8: u8 = 0x11;
+    pub(super) const I16: u8 = 0x12;
+    pub(super) const I32: u8 = 0x13;
+    pub(super) const I64: u8 = 0x14;
+    pub(super) const F32: u8 = 0x21;
+    pub(super) const F64: u8 = 0x22;
+    pub(super) const BOOL: u8 = 0x30;
+    pub(super) const CHAR: u8 = 0x31;
+    pub(super) const STR: u8 = 0x32;
+}
+
+/// Error returned when a frame or format entry can't be parsed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// The data doesn't start with [FRAME_START].
+    NotAFrame,
+
+    /// The data ends part way through, e.g. because the capture was cut short.
+    Truncated,
+
+    /// An argument has a type byte this version of the crate doesn't know about.
+    UnknownArgType(u8),
+
+    /// An argument's value isn't valid for its type, e.g. a string that isn't UTF-8.
+    InvalidArg(u8),
+
+    /// A format entry's level or strings are invalid.
+    InvalidEntry,
+}
+
+impl Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::NotAFrame => write!(f, "not a binary record"),
+            FrameError::Truncated => write!(f, "binary record is truncated"),
+            FrameError::UnknownArgType(tag) => write!(f, "unknown argument type {:#04x}", tag),
+            FrameError::InvalidArg(tag) => {
+                write!(f, "invalid argument of type {:#04x}", tag)
+            }
+            FrameError::InvalidEntry => write!(f, "invalid format entry"),
+        }
+    }
+}
+
+/// An argument of a binary record, as read back from a frame.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ArgValue<'a> {
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+    Char(char),
+    Str(&'a str),
+}
+
+/// The level, target and format string of a binary record, as stored in [FORMAT_SECTION].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FormatEntry<'a> {
+    pub level: Level,
+    pub target: &'a str,
+    pub format: &'a str,
+}
+
+/// A parsed frame, borrowing from the raw data.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Frame<'a> {
+    /// Address the record's [FormatEntry] was loaded at.
+    pub id: u32,
+    args: &'a [u8],
+}
+
+/// Iterator over the arguments of a [Frame].
+pub struct Args<'a> {
+    reader: Reader<'a>,
+}
+
+/// Cursor over little-endian data.
+#[derive(Copy, Clone, Debug)]
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], FrameError> {
+        if self.data.len() < len {
+            return Err(FrameError::Truncated);
+        }
+
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], FrameError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, FrameError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, FrameError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, FrameError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn str(&mut self) -> Result<Option<&'a str>, FrameError> {
+        let len = self.u16()? as usize;
+        Ok(core::str::from_utf8(self.bytes(len)?).ok())
+    }
+}
+
+impl<'a> FormatEntry<'a> {
+    /// Parse the format entry at the start of `data`.
+    pub fn parse(data: &'a [u8]) -> Result<Self, FrameError> {
+        let mut reader = Reader { data };
+
+        let level = match reader.u8()? {
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            5 => Level::Trace,
+            _ => return Err(FrameError::InvalidEntry),
+        };
+        let target = reader.str()?.ok_or(FrameError::InvalidEntry)?;
+        let format = reader.str()?.ok_or(FrameError::InvalidEntry)?;
+
+        Ok(FormatEntry {
+            level,
+            target,
+            format,
+        })
+    }
+}
+
+impl<'a> Frame<'a> {
+    /// Parse the frame at the start of `data`.
+    ///
+    /// Returns the frame along with its length in bytes, so that the data after it can be read.
+    /// Arguments are only parsed as they are read with [args](Self::args).
+    pub fn parse(data: &'a [u8]) -> Result<(Self, usize), FrameError> {
+        let mut reader = Reader { data };
+
+        if reader.u8().ok() != Some(FRAME_START) {
+            return Err(FrameError::NotAFrame);
+        }
+
+        let len = reader.u16()? as usize;
+        let mut body = Reader {
+            data: reader.bytes(len)?,
+        };
+        let id = body.u32()?;
+
+        Ok((
+            Frame {
+                id,
+                args: body.data,
+            },
+            3 + len,
+        ))
+    }
+
+    /// The arguments of the record, in the order they were logged.
+    ///
+    /// Iteration stops after the first error.
+    pub fn args(&self) -> Args<'a> {
+        Args {
+            reader: Reader { data: self.args },
+        }
+    }
+}
+
+impl<'a> Args<'a> {
+    fn read(&mut self) -> Result<ArgValue<'a>, FrameError> {
+        let tag = self.reader.u8()?;
+        let reader = &mut self.reader;
+
+        let value = match tag {
+            tag::U8 => ArgValue::U8(reader.u8()?),
+            tag::U16 => ArgValue::U16(reader.u16()?),
+            tag::U32 => ArgValue::U32(reader.u32()?),
+            tag::U64 => ArgValue::U64(u64::from_le_bytes(reader.array()?)),
+            tag::I8 => ArgValue::I8(i8::from_le_bytes(reader.array()?)),
+            tag::I16 => ArgValue::I16(i16::from_le_bytes(reader.array()?)),
+            tag::I32 => ArgValue::I32(i32::from_le_bytes(reader.array()?)),
+            tag::I64 => ArgValue::I64(i64::from_le_bytes(reader.array()?)),
+            tag::F32 => ArgValue::F32(f32::from_le_bytes(reader.array()?)),
+            tag::F64 => ArgValue::F64(f64::from_le_bytes(reader.array()?)),
+            tag::BOOL => match reader.u8()? {
+                0 => ArgValue::Bool(false),
+                1 => ArgValue::Bool(true),
+                _ => return Err(FrameError::InvalidArg(tag)),
+            },
+            tag::CHAR => {
+                ArgValue::Char(char::from_u32(reader.u32()?).ok_or(FrameError::InvalidArg(tag))?)
+            }
+            tag::STR => ArgValue::Str(reader.str()?.ok_or(FrameError::InvalidArg(tag))?),
+            tag => return Err(FrameError::UnknownArgType(tag)),
+        };
+
+        Ok(value)
+    }
+}
+
+impl<'a> Iterator for Args<'a> {
+    type Item = Result<ArgValue<'a>, FrameError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.reader.data.is_empty() {
+            return None;
+        }
+
+        let result = self.read();
+        if result.is_err() {
+            self.reader.data = &[];
+        }
+
+        Some(result)
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A value that can be an argument of [log_binary](crate::log_binary).
+///
+/// Implemented for the integer and floating point types, `bool`, `char` and `str`, along with
+/// references to them. Other types have to be converted first, e.g. with `String::as_str`.
+pub trait BinaryArg: sealed::Sealed {
+    /// Append the argument to `frame`.
+    fn encode(&self, frame: &mut FrameWriter);
+}
+
+macro_rules! impl_binary_arg {
+    ($($ty:ty => $tag:expr),* $(,)?) => {
+        $(
+            impl sealed::Sealed for $ty {}
+
+            impl BinaryArg for $ty {
+                fn encode(&self, frame: &mut FrameWriter) {
+                    frame.push($tag, &[&self.to_le_bytes()]);
+                }
+            }
+        )*
+    };
+}
+
+impl_binary_arg!(
+    u8 => tag::U8,
+    u16 => tag::U16,
+    u32 => tag::U32,
+    u64 => tag::U64,
+    i8 => tag::I8,
+    i16 => tag::I16,
+    i32 => tag::I32,
+    i64 => tag::I64,
+    f32 => tag::F32,
+    f64 => tag::F64,
+);
+
+impl sealed::Sealed for usize {}
+
+/// Written as a `u32` on the PSP, and a `u64` on 64-bit hosts.
+impl BinaryArg for usize {
+    fn encode(&self, frame: &mut FrameWriter) {
+        if usize::BITS == 32 {
+            (*self as u32).encode(frame);
+        } else {
+            (*self as u64).encode(frame);
+        }
+    }
+}
+
+impl sealed::Sealed for isize {}
+
+/// Written as an `i32` on the PSP, and an `i64` on 64-bit hosts.
+impl BinaryArg for isize {
+    fn encode(&self, frame: &mut FrameWriter) {
+        if isize::BITS == 32 {
+            (*self as i32).encode(frame);
+        } else {
+            (*self as i64).encode(frame);
+        }
+    }
+}
+
+impl sealed::Sealed for bool {}
+
+impl BinaryArg for bool {
+    fn encode(&self, frame: &mut FrameWriter) {
+        frame.push(tag::BOOL, &[&[*self as u8]]);
+    }
+}
+
+impl sealed::Sealed for char {}
+
+impl BinaryArg for char {
+    fn encode(&self, frame: &mut FrameWriter) {
+        frame.push(tag::CHAR, &[&(*self as u32).to_le_bytes()]);
+    }
+}
+
+impl sealed::Sealed for str {}
+
+/// Cut short at a character boundary if the rest of the frame can't hold all of it.
+impl BinaryArg for str {
+    fn encode(&self, frame: &mut FrameWriter) {
+        let room = MAX_FRAME_LEN
+            .saturating_sub(frame.len + 3)
+            .min(u16::MAX as usize);
+        let mut len = self.len().min(room);
+        while !self.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        frame.push(
+            tag::STR,
+            &[&(len as u16).to_le_bytes(), &self.as_bytes()[..len]],
+        );
+
+        if len < self.len() {
+            frame.full = true;
+        }
+    }
+}
+
+impl<T: BinaryArg + ?Sized> sealed::Sealed for &T {}
+
+impl<T: BinaryArg + ?Sized> BinaryArg for &T {
+    fn encode(&self, frame: &mut FrameWriter) {
+        (**self).encode(frame)
+    }
+}
+
+/// Builder for a frame, held on the stack.
+///
+/// # Examples
+/// ```
+/// use psp_logger::binary::{ArgValue, Frame, FrameWriter};
+///
+/// let mut writer = FrameWriter::new(0x0880_9000);
+/// writer.arg(&42u32);
+/// writer.arg(&"ok");
+///
+/// let (frame, _) = Frame::parse(writer.finish()).unwrap();
+/// assert_eq!(frame.id, 0x0880_9000);
+/// assert_eq!(frame.args().next(), Some(Ok(ArgValue::U32(42))));
+/// ```
+pub struct FrameWriter {
+    buf: [u8; MAX_FRAME_LEN],
+    len: usize,
+
+    /// Whether an argument has been left out or cut short, so later ones must be left out too.
+    full: bool,
+}
+
+impl FrameWriter {
+    /// Start a frame for the record with the given id.
+    pub fn new(id: u32) -> Self {
+        let mut writer = FrameWriter {
+            buf: [0; MAX_FRAME_LEN],
+            len: HEADER_LEN,
+            full: false,
+        };
+
+        writer.buf[0] = FRAME_START;
+        writer.buf[3..HEADER_LEN].copy_from_slice(&id.to_le_bytes());
+        writer
+    }
+
+    /// Append an argument, unless the frame is full.
+    pub fn arg(&mut self, arg: &dyn BinaryArg) {
+        arg.encode(self);
+    }
+
+    /// Fill in the frame's length, returning the finished frame.
+    pub fn finish(&mut self) -> &[u8] {
+        let len = (self.len - 3) as u16;
+        self.buf[1..3].copy_from_slice(&len.to_le_bytes());
+
+        &self.buf[..self.len]
+    }
+
+    /// Append an argument made up of `tag` followed by each of `parts`, if all of it fits.
+    fn push(&mut self, tag: u8, parts: &[&[u8]]) {
+        let len = 1 + parts.iter().map(|part| part.len()).sum::<usize>();
+
+        if self.full || self.len + len > MAX_FRAME_LEN {
+            self.full = true;
+            return;
+        }
+
+        self.buf[self.len] = tag;
+        self.len += 1;
+
+        for part in parts {
+            self.buf[self.len..self.len + part.len()].copy_from_slice(part);
+            self.len += part.len();
+        }
+    }
+}
+
+/// Log a compact binary record.
+///
+/// Works like [log::log], but the record is written to its sinks as a frame holding the
+/// arguments' raw bytes rather than formatted text. See [binary](crate::binary) for the
+/// format, and the `psp-logdecode` tool in this repository for turning frames back into text.
+///
+/// The level must be a constant, and the format string a literal. Arguments have to implement
+/// [BinaryArg](crate::binary::BinaryArg), and must be passed explicitly rather than captured by
+/// name in the format string.
+///
+/// Frames are only written to sinks that implement [LogSink::write_binary](crate::LogSink),
+/// such as [StdioSink](crate::StdioSink) and [FileSink](crate::FileSink). Records logged in
+/// interrupt context are dropped, and counted by
+/// [PspLogger::interrupt_records_dropped](crate::PspLogger::interrupt_records_dropped).
+///
+/// # Examples
+/// ```
+/// use log::Level;
+/// use psp_logger::log_binary;
+///
+/// let frame = 1200u32;
+/// let name = "player";
+/// log_binary!(Level::Info, "{} spawned on frame {}", name, frame);
+/// log_binary!(Level::Debug, "velocity {:.2}, {:.2}", 0.5f32, -1.25f32);
+/// ```
+#[macro_export]
+macro_rules! log_binary {
+    ($level:expr, $format:literal $(, $arg:expr)* $(,)?) => {{
+        const __PSP_LOGGER_LEN: usize = $crate::binary::__entry_len(module_path!(), $format);
+
+        #[cfg_attr(target_os = "psp", link_section = "psp_logger_fmt")]
+        static __PSP_LOGGER_ENTRY: [u8; __PSP_LOGGER_LEN] =
+            $crate::binary::__entry($level, module_path!(), $format);
+
+        // Check the arguments against the format string, as `format_args!` would.
+        if false {
+            let _ = format_args!($format $(, $arg)*);
+        }
+
+        $crate::binary::__write(
+            &__PSP_LOGGER_ENTRY,
+            $level,
+            module_path!(),
+            &[$(&$arg as &dyn $crate::binary::BinaryArg),*],
+        );
+    }};
+}
+
+/// Length of the [FormatEntry] for a record with the given target and format string.
+#[doc(hidden)]
+pub const fn __entry_len(target: &str, format: &str) -> usize {
+    1 + 2 + target.len() + 2 + format.len()
+}
+
+/// Build the [FormatEntry] for a record, at compile time.
+#[doc(hidden)]
+pub const fn __entry<const LEN: usize>(level: Level, target: &str, format: &str) -> [u8; LEN] {
+    let mut entry = [0; LEN];
+
+    entry[0] = level as u8;
+    let end = put_str(&mut entry, 1, target);
+    put_str(&mut entry, end, format);
+
+    entry
+}
+
+/// Write `s` into `entry` at `start` as a `u16` length followed by its bytes, returning the end.
+const fn put_str(entry: &mut [u8], start: usize, s: &str) -> usize {
+    assert!(s.len() <= u16::MAX as usize, "format string is too long");
+
+    let len = (s.len() as u16).to_le_bytes();
+    entry[start] = len[0];
+    entry[start + 1] = len[1];
+
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        entry[start + 2 + i] = bytes[i];
+        i += 1;
+    }
+
+    start + 2 + bytes.len()
+}
+
+/// Encode a record logged with [log_binary](crate::log_binary) and write it through the global
+/// logger.
+#[doc(hidden)]
+pub fn __write(entry: &'static [u8], level: Level, target: &'static str, args: &[&dyn BinaryArg]) {
+    if level > log::max_level() {
+        return;
+    }
+
+    let metadata = Metadata::builder().level(level).target(target).build();
+
+    // Frames can't be queued with the formatted lines of other records logged in interrupt
+    // context, so are counted as lost.
+    if interrupt::in_interrupt() {
+        let enabled = LOGGER_CONF.try_read().is_none_or(|config| {
+            config
+                .as_ref()
+                .is_some_and(|config| config.enabled(&metadata))
+        });
+        if enabled {
+            INTERRUPT_RECORDS.count_dropped();
+        }
+        return;
+    }
+
+    let reentrancy = reentrancy::enter();
+    if reentrancy.depth() > 1 {
+        NESTED_RECORDS_DROPPED.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+
+    let config = LOGGER_CONF.read();
+    let Some(config) = config.as_ref().filter(|config| config.enabled(&metadata)) else {
+        return;
+    };
+
+    let mut frame = FrameWriter::new(entry.as_ptr() as usize as u32);
+    for arg in args {
+        frame.arg(*arg);
+    }
+    let frame = frame.finish();
+
+    match reentrancy.depth() {
+        0 => {
+            INTERRUPT_RECORDS.drain(config);
+            write_frame(config, level, frame);
+        }
+        // As with nested text records, skip the sinks the outer record may be using.
+        _ => StdioSink.write_binary(config.get_stream(level), frame),
+    }
+}
+
+/// Write a frame to the sinks for `level`.
+fn write_frame(config: &PspLoggerConfig, level: Level, frame: &[u8]) {
+    write_to_sinks(
+        config,
+        level,
+        || Some(RECORD_LOCK.lock()),
+        |destinations, stream| {
+            for sink in destinations.iter() {
+                sink.write_binary(stream, frame);
+            }
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OutputStream, RingBuffer};
+    use std::boxed::Box;
+    use std::string::ToString;
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    fn args(frame: &[u8]) -> Vec<Result<ArgValue<'_>, FrameError>> {
+        let (frame, len) = Frame::parse(frame).unwrap();
+        assert_eq!(len, frame.args.len() + HEADER_LEN);
+
+        frame.args().collect()
+    }
+
+    #[test]
+    fn arguments_round_trip() {
+        let mut writer = FrameWriter::new(0x0880_9000);
+        let values: [&dyn BinaryArg; 14] = [
+            &0xabu8,
+            &0xabcdu16,
+            &0xdead_beefu32,
+            &u64::MAX,
+            &-1i8,
+            &-300i16,
+            &-70_000i32,
+            &i64::MIN,
+            &1.5f32,
+            &-0.25f64,
+            &true,
+            &'é',
+            &"psp",
+            &7usize,
+        ];
+        for value in values {
+            writer.arg(value);
+        }
+
+        let frame = writer.finish();
+        assert_eq!(Frame::parse(frame).unwrap().0.id, 0x0880_9000);
+        assert_eq!(
+            args(frame),
+            [
+                Ok(ArgValue::U8(0xab)),
+                Ok(ArgValue::U16(0xabcd)),
+                Ok(ArgValue::U32(0xdead_beef)),
+                Ok(ArgValue::U64(u64::MAX)),
+                Ok(ArgValue::I8(-1)),
+                Ok(ArgValue::I16(-300)),
+                Ok(ArgValue::I32(-70_000)),
+                Ok(ArgValue::I64(i64::MIN)),
+                Ok(ArgValue::F32(1.5)),
+                Ok(ArgValue::F64(-0.25)),
+                Ok(ArgValue::Bool(true)),
+                Ok(ArgValue::Char('é')),
+                Ok(ArgValue::Str("psp")),
+                Ok(ArgValue::U64(7)),
+            ]
+        );
+    }
+
+    #[test]
+    fn long_strings_are_cut_short_and_later_arguments_left_out() {
+        let long = "é".repeat(MAX_FRAME_LEN);
+
+        let mut writer = FrameWriter::new(0);
+        writer.arg(&1u8);
+        writer.arg(&long.as_str());
+        writer.arg(&2u8);
+        let frame = writer.finish();
+
+        assert!(frame.len() <= MAX_FRAME_LEN);
+        let args = args(frame);
+        assert_eq!(args.len(), 2);
+        let Ok(ArgValue::Str(cut)) = args[1] else {
+            panic!("expected a string, got {:?}", args[1]);
+        };
+        assert!(long.starts_with(cut));
+        assert!(cut.len() >= MAX_FRAME_LEN - HEADER_LEN - 2 - 4 - 1);
+    }
+
+    #[test]
+    fn entry_round_trip() {
+        const ENTRY: [u8; __entry_len("game::physics", "x = {}")] =
+            __entry(Level::Debug, "game::physics", "x = {}");
+
+        assert_eq!(
+            FormatEntry::parse(&ENTRY),
+            Ok(FormatEntry {
+                level: Level::Debug,
+                target: "game::physics",
+                format: "x = {}",
+            })
+        );
+        assert_eq!(
+            FormatEntry::parse(&[6, 0, 0, 0, 0]),
+            Err(FrameError::InvalidEntry)
+        );
+        assert_eq!(FormatEntry::parse(&ENTRY[..9]), Err(FrameError::Truncated));
+    }
+
+    #[test]
+    fn bad_frames_are_rejected() {
+        let mut writer = FrameWriter::new(0);
+        writer.arg(&1u32);
+        let frame = writer.finish().to_vec();
+
+        assert_eq!(Frame::parse(b"text\n"), Err(FrameError::NotAFrame));
+        assert_eq!(
+            Frame::parse(&frame[..frame.len() - 1]),
+            Err(FrameError::Truncated)
+        );
+
+        let mut unknown = frame.clone();
+        unknown[HEADER_LEN] = 0x7f;
+        assert_eq!(args(&unknown), [Err(FrameError::UnknownArgType(0x7f))]);
+
+        assert_eq!(
+            FrameError::UnknownArgType(0x7f).to_string(),
+            "unknown argument type 0x7f"
+        );
+    }
+
+    /// Sink recording the frames written to it.
+    #[derive(Default)]
+    struct FrameSink {
+        frames: Mutex<Vec<(OutputStream, Vec<u8>)>>,
+    }
+
+    impl LogSink for FrameSink {
+        fn write(&self, _stream: OutputStream, _line: &str) {}
+
+        fn write_binary(&self, stream: OutputStream, frame: &[u8]) {
+            self.frames.lock().unwrap().push((stream, frame.to_vec()));
+        }
+    }
+
+    #[test]
+    fn frames_are_only_kept_by_sinks_that_take_them() {
+        let frames: &'static FrameSink = Box::leak(Box::default());
+        let ring: &'static RingBuffer<4> = Box::leak(Box::new(RingBuffer::new()));
+        let config = PspLoggerConfig::new(log::LevelFilter::Trace)
+            .with_info_destinations(crate::Destinations::new().with(frames).with(ring));
+
+        write_frame(&config, Level::Info, &[FRAME_START, 4, 0, 1, 2, 3, 4]);
+
+        assert_eq!(
+            *frames.frames.lock().unwrap(),
+            [(
+                OutputStream::StdErr,
+                [FRAME_START, 4, 0, 1, 2, 3, 4].to_vec()
+            )]
+        );
+        let mut lines = 0;
+        ring.for_each(|_| lines += 1);
+        assert_eq!(lines, 0);
+    }
+}
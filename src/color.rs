@@ -0,0 +1,141 @@
+//! ANSI colouring of log lines.
+
+use log::Level;
+
+/// A colour or style that can be applied to a log line using ANSI escape codes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Color {
+    /// Leave the line uncoloured.
+    None,
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    BrightRed,
+    BrightGreen,
+    BrightYellow,
+    BrightBlue,
+    BrightMagenta,
+    BrightCyan,
+    BrightWhite,
+    /// Faint text, for less important output.
+    Dim,
+}
+
+/// The [Color] used for each log level.
+///
+/// The default colours are red for errors, yellow for warnings, green for info, blue for debug
+/// and dim for trace.
+///
+/// # Examples
+/// ```
+/// use psp_logger::{Color, LevelColors, PspLoggerConfig};
+///
+/// let config = PspLoggerConfig::new(log::LevelFilter::Trace)
+///     .with_colors(LevelColors::default().with_info(Color::None));
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LevelColors {
+    error: Color,
+    warn: Color,
+    info: Color,
+    debug: Color,
+    trace: Color,
+}
+
+/// Escape sequence resetting all colours and styles.
+pub(crate) const RESET: &str = "\x1b[0m";
+
+impl Color {
+    /// The escape sequence that switches to this colour.
+    pub const fn escape(self) -> &'static str {
+        match self {
+            Color::None => "",
+            Color::Black => "\x1b[30m",
+            Color::Red => "\x1b[31m",
+            Color::Green => "\x1b[32m",
+            Color::Yellow => "\x1b[33m",
+            Color::Blue => "\x1b[34m",
+            Color::Magenta => "\x1b[35m",
+            Color::Cyan => "\x1b[36m",
+            Color::White => "\x1b[37m",
+            Color::BrightRed => "\x1b[91m",
+            Color::BrightGreen => "\x1b[92m",
+            Color::BrightYellow => "\x1b[93m",
+            Color::BrightBlue => "\x1b[94m",
+            Color::BrightMagenta => "\x1b[95m",
+            Color::BrightCyan => "\x1b[96m",
+            Color::BrightWhite => "\x1b[97m",
+            Color::Dim => "\x1b[2m",
+        }
+    }
+}
+
+impl LevelColors {
+    /// Set the colour used for error logs.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_error(mut self, color: Color) -> Self {
+        self.error = color;
+        self
+    }
+
+    /// Set the colour used for warn logs.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_warn(mut self, color: Color) -> Self {
+        self.warn = color;
+        self
+    }
+
+    /// Set the colour used for info logs.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_info(mut self, color: Color) -> Self {
+        self.info = color;
+        self
+    }
+
+    /// Set the colour used for debug logs.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_debug(mut self, color: Color) -> Self {
+        self.debug = color;
+        self
+    }
+
+    /// Set the colour used for trace logs.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_trace(mut self, color: Color) -> Self {
+        self.trace = color;
+        self
+    }
+
+    /// The colour used for a log level.
+    pub fn get(&self, level: Level) -> Color {
+        match level {
+            Level::Error => self.error,
+            Level::Warn => self.warn,
+            Level::Info => self.info,
+            Level::Debug => self.debug,
+            Level::Trace => self.trace,
+        }
+    }
+}
+
+impl Default for LevelColors {
+    fn default() -> Self {
+        LevelColors {
+            error: Color::Red,
+            warn: Color::Yellow,
+            info: Color::Green,
+            debug: Color::Blue,
+            trace: Color::Dim,
+        }
+    }
+}
@@ -0,0 +1,417 @@
+//! Settings read from a text file, applied on top of a [PspLoggerConfig].
+//!
+//! Each line holds a `key = value` setting. Blank lines and lines starting with `#` are
+//! ignored. The keys are:
+//! - `level`: The level filter, e.g. `debug`. This replaces the level of a directive without a
+//!   target, whether it was set in code or earlier in the file.
+//! - `flush_level`: The level at or above which sinks are flushed after each record.
+//! - `backtrace_level`: The level at or above which records are followed by a backtrace.
+//! - `format`: The line template, as accepted by
+//!   [with_format](crate::PspLoggerConfig::with_format).
+//! - `directives`: Per-target [Directives], e.g. `warn,my_game::render=trace`.
+//! - `stream`, and `error_stream` to `trace_stream`: `stdout` or `stderr`, for all levels or
+//!   just one.
+//! - `sinks`, and `error_sinks` to `trace_sinks`: A comma separated list of sink names, for all
+//!   levels or just one. `stdio` is always available; other names are given by the
+//!   application.
+
+use core::fmt::{self, Display};
+use core::str::FromStr;
+
+use log::{Level, LevelFilter};
+
+use crate::format::{Template, MAX_TEMPLATE_LEN};
+use crate::sink::read_file;
+use crate::{
+    Destinations, DirectiveError, Directives, LogSink, OutputStream, PspLoggerConfig, StdioSink,
+    MAX_DESTINATIONS,
+};
+
+/// Largest settings file that can be read, in bytes.
+const MAX_FILE_LEN: usize = 2048;
+
+const LEVELS: [Level; 5] = [
+    Level::Error,
+    Level::Warn,
+    Level::Info,
+    Level::Debug,
+    Level::Trace,
+];
+
+/// Error returned when a settings file can't be read or applied.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The file couldn't be read, with the PSP error code.
+    Io(i32),
+
+    /// The file isn't valid UTF-8.
+    InvalidUtf8,
+
+    /// A line of the file is invalid. Lines are numbered from 1.
+    Invalid { line: usize, kind: ConfigErrorKind },
+}
+
+/// The ways a line of a settings file can be invalid.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConfigErrorKind {
+    /// The line isn't blank or a comment, but has no `=`.
+    MissingEquals,
+
+    /// The key isn't one of the known settings.
+    UnknownKey,
+
+    /// The value isn't a log level.
+    InvalidLevel,
+
+    /// The value isn't `stdout` or `stderr`.
+    InvalidStream,
+
+    /// A sink name wasn't given by the application.
+    UnknownSink,
+
+    /// More than [MAX_DESTINATIONS] sinks are listed.
+    TooManySinks,
+
+    /// The format is longer than [MAX_TEMPLATE_LEN] bytes.
+    FormatTooLong,
+
+    /// The directives couldn't be parsed.
+    InvalidDirectives(DirectiveError),
+}
+
+/// Apply the settings in `text` on top of `config`.
+///
+/// Sink names are looked up in `sinks`, and then `stdio` refers to [StdioSink].
+pub(crate) fn apply(
+    mut config: PspLoggerConfig,
+    text: &str,
+    sinks: &[(&str, &'static dyn LogSink)],
+) -> Result<PspLoggerConfig, ConfigError> {
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        config = apply_setting(config, line, sinks).map_err(|kind| ConfigError::Invalid {
+            line: index + 1,
+            kind,
+        })?;
+    }
+
+    Ok(config)
+}
+
+/// Read the file at `path` and apply its settings on top of `config`.
+pub(crate) fn apply_file(
+    config: PspLoggerConfig,
+    path: &str,
+    sinks: &[(&str, &'static dyn LogSink)],
+) -> Result<PspLoggerConfig, ConfigError> {
+    let mut buf = [0; MAX_FILE_LEN];
+    let len = read_file(path, &mut buf).map_err(ConfigError::Io)?;
+    let text = core::str::from_utf8(&buf[..len]).map_err(|_| ConfigError::InvalidUtf8)?;
+
+    apply(config, text, sinks)
+}
+
+fn apply_setting(
+    mut config: PspLoggerConfig,
+    line: &str,
+    sinks: &[(&str, &'static dyn LogSink)],
+) -> Result<PspLoggerConfig, ConfigErrorKind> {
+    let (key, value) = line.split_once('=').ok_or(ConfigErrorKind::MissingEquals)?;
+    let (key, value) = (key.trim(), value.trim());
+
+    // Stream and sink settings may be prefixed with the level they apply to.
+    let (level, setting) = match key.split_once('_') {
+        Some((level, setting)) => match Level::from_str(level) {
+            Ok(level) => (Some(level), setting),
+            Err(_) => (None, key),
+        },
+        None => (None, key),
+    };
+    let levels = match level {
+        Some(level) => core::slice::from_ref(&LEVELS[level as usize - 1]),
+        None => &LEVELS[..],
+    };
+
+    match (level, setting) {
+        (None, "level") => config = config.with_level_filter(parse_level(value)?),
+        (None, "flush_level") => config = config.with_flush_level(parse_level(value)?),
+        (None, "backtrace_level") => {
+            config = config.with_backtrace_level(parse_level(value)?);
+        }
+        (None, "format") => {
+            config.format = Template::copy_from(value).ok_or(ConfigErrorKind::FormatTooLong)?;
+        }
+        (None, "directives") => {
+            let directives =
+                Directives::parse(value).map_err(ConfigErrorKind::InvalidDirectives)?;
+            config = config.with_directives(directives);
+        }
+        (_, "stream") => {
+            let stream = if value.eq_ignore_ascii_case("stdout") {
+                OutputStream::StdOut
+            } else if value.eq_ignore_ascii_case("stderr") {
+                OutputStream::StdErr
+            } else {
+                return Err(ConfigErrorKind::InvalidStream);
+            };
+
+            for &level in levels {
+                config = with_stream(config, level, stream);
+            }
+        }
+        (_, "sinks") => {
+            let destinations = parse_sinks(value, sinks)?;
+
+            for &level in levels {
+                config = with_destinations(config, level, destinations);
+            }
+        }
+        _ => return Err(ConfigErrorKind::UnknownKey),
+    }
+
+    Ok(config)
+}
+
+fn parse_level(value: &str) -> Result<LevelFilter, ConfigErrorKind> {
+    LevelFilter::from_str(value).map_err(|_| ConfigErrorKind::InvalidLevel)
+}
+
+fn parse_sinks(
+    value: &str,
+    sinks: &[(&str, &'static dyn LogSink)],
+) -> Result<Destinations, ConfigErrorKind> {
+    let mut destinations = Destinations::new();
+    let names = value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty());
+
+    for (count, name) in names.enumerate() {
+        let sink = match sinks.iter().find(|(sink_name, _)| *sink_name == name) {
+            Some((_, sink)) => *sink,
+            None if name == "stdio" => &StdioSink,
+            None => return Err(ConfigErrorKind::UnknownSink),
+        };
+
+        if count == MAX_DESTINATIONS {
+            return Err(ConfigErrorKind::TooManySinks);
+        }
+
+        destinations = destinations.with(sink);
+    }
+
+    Ok(destinations)
+}
+
+fn with_stream(config: PspLoggerConfig, level: Level, stream: OutputStream) -> PspLoggerConfig {
+    match level {
+        Level::Error => config.with_error_stream(stream),
+        Level::Warn => config.with_warn_stream(stream),
+        Level::Info => config.with_info_stream(stream),
+        Level::Debug => config.with_debug_stream(stream),
+        Level::Trace => config.with_trace_stream(stream),
+    }
+}
+
+fn with_destinations(
+    config: PspLoggerConfig,
+    level: Level,
+    destinations: Destinations,
+) -> PspLoggerConfig {
+    match level {
+        Level::Error => config.with_error_destinations(destinations),
+        Level::Warn => config.with_warn_destinations(destinations),
+        Level::Info => config.with_info_destinations(destinations),
+        Level::Debug => config.with_debug_destinations(destinations),
+        Level::Trace => config.with_trace_destinations(destinations),
+    }
+}
+
+impl Display for ConfigErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigErrorKind::MissingEquals => write!(f, "expected `key = value`"),
+            ConfigErrorKind::UnknownKey => write!(f, "unknown setting"),
+            ConfigErrorKind::InvalidLevel => write!(f, "invalid log level"),
+            ConfigErrorKind::InvalidStream => write!(f, "expected `stdout` or `stderr`"),
+            ConfigErrorKind::UnknownSink => write!(f, "unknown sink"),
+            ConfigErrorKind::TooManySinks => {
+                write!(f, "more than {} sinks", MAX_DESTINATIONS)
+            }
+            ConfigErrorKind::FormatTooLong => {
+                write!(f, "format longer than {} bytes", MAX_TEMPLATE_LEN)
+            }
+            ConfigErrorKind::InvalidDirectives(error) => error.fmt(f),
+        }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(code) => write!(f, "could not read settings: {:#010x}", code),
+            ConfigError::InvalidUtf8 => write!(f, "settings file is not valid UTF-8"),
+            ConfigError::Invalid { line, kind } => write!(f, "line {}: {}", line, kind),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host;
+    use crate::RingBuffer;
+    use log::Metadata;
+
+    static RING: RingBuffer<4> = RingBuffer::new();
+
+    fn sinks() -> [(&'static str, &'static dyn LogSink); 1] {
+        [("ring", &RING)]
+    }
+
+    fn invalid(text: &str) -> ConfigError {
+        match apply(PspLoggerConfig::new(LevelFilter::Info), text, &sinks()) {
+            Ok(_) => panic!("settings should be invalid"),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn settings_are_applied() {
+        let text = "\
+            # Verbose build for QA\n\
+            level = trace\n\
+            \n\
+            stream = stdout\n\
+            error_stream = stderr\n\
+            format = {level} {msg}\n\
+            directives = my_game::audio=off\n\
+            sinks = stdio, ring\n\
+            trace_sinks = ring\n\
+            flush_level = warn\n\
+            backtrace_level = off\n";
+
+        let config = apply(PspLoggerConfig::new(LevelFilter::Info), text, &sinks()).unwrap();
+
+        assert_eq!(config.level_filter, LevelFilter::Trace);
+        assert_eq!(config.flush_level, LevelFilter::Warn);
+        assert_eq!(config.backtrace_level, LevelFilter::Off);
+        assert_eq!(config.format.as_str(), "{level} {msg}");
+        assert_eq!(
+            config.directives.level_for("my_game::audio"),
+            Some(LevelFilter::Off)
+        );
+        assert_eq!(config.get_stream(Level::Error), OutputStream::StdErr);
+        assert_eq!(config.get_stream(Level::Warn), OutputStream::StdOut);
+        assert_eq!(config.get_destinations(Level::Debug).iter().count(), 2);
+        assert_eq!(config.get_destinations(Level::Trace).iter().count(), 1);
+    }
+
+    #[test]
+    fn unset_settings_keep_programmatic_values() {
+        let config = PspLoggerConfig::new(LevelFilter::Debug).with_format("{msg}!");
+        let config = apply(config, "warn_stream = stdout", &sinks()).unwrap();
+
+        assert_eq!(config.level_filter, LevelFilter::Debug);
+        assert_eq!(config.format.as_str(), "{msg}!");
+        assert_eq!(config.get_stream(Level::Warn), OutputStream::StdOut);
+        assert_eq!(config.get_stream(Level::Info), OutputStream::StdErr);
+    }
+
+    #[test]
+    fn level_replaces_the_default_directive() {
+        let config = PspLoggerConfig::new(LevelFilter::Info)
+            .with_directives(Directives::parse("warn,my_game::render=trace").unwrap());
+        let config = apply(config, "level = debug", &sinks()).unwrap();
+
+        assert_eq!(config.directives.default_level(), None);
+        assert_eq!(
+            config.directives.level_for("my_game::render"),
+            Some(LevelFilter::Trace)
+        );
+        assert!(config.enabled(
+            &Metadata::builder()
+                .level(Level::Debug)
+                .target("other")
+                .build()
+        ));
+
+        let config = apply(config, "directives = error", &sinks()).unwrap();
+        assert!(!config.enabled(
+            &Metadata::builder()
+                .level(Level::Warn)
+                .target("other")
+                .build()
+        ));
+    }
+
+    #[test]
+    fn errors_report_line_numbers() {
+        let invalid_line = |line, kind| ConfigError::Invalid { line, kind };
+
+        assert_eq!(
+            invalid("level = info\nlevel info"),
+            invalid_line(2, ConfigErrorKind::MissingEquals)
+        );
+        assert_eq!(
+            invalid("# colours\ncolours = on"),
+            invalid_line(2, ConfigErrorKind::UnknownKey)
+        );
+        assert_eq!(
+            invalid("level = loud"),
+            invalid_line(1, ConfigErrorKind::InvalidLevel)
+        );
+        assert_eq!(
+            invalid("\n\ninfo_stream = screen"),
+            invalid_line(3, ConfigErrorKind::InvalidStream)
+        );
+        assert_eq!(
+            invalid("sinks = ring, file"),
+            invalid_line(1, ConfigErrorKind::UnknownSink)
+        );
+        assert_eq!(
+            invalid("sinks = ring, ring, ring, ring, ring"),
+            invalid_line(1, ConfigErrorKind::TooManySinks)
+        );
+        assert_eq!(
+            invalid("directives = a=loud"),
+            invalid_line(
+                1,
+                ConfigErrorKind::InvalidDirectives(DirectiveError::InvalidLevel(0))
+            )
+        );
+
+        let format = std::format!("format = {}", "x".repeat(MAX_TEMPLATE_LEN + 1));
+        assert_eq!(
+            invalid(&format),
+            invalid_line(1, ConfigErrorKind::FormatTooLong)
+        );
+    }
+
+    #[test]
+    fn error_display() {
+        assert_eq!(
+            std::format!("{}", invalid("\nlevel = loud")),
+            "line 2: invalid log level"
+        );
+    }
+
+    #[test]
+    fn read_from_file() {
+        host::add_file("ms0:/logging.cfg", b"level = error\r\nstream = stdout\r\n");
+
+        let config = apply_file(
+            PspLoggerConfig::new(LevelFilter::Info),
+            "ms0:/logging.cfg",
+            &sinks(),
+        )
+        .unwrap();
+
+        assert_eq!(config.level_filter, LevelFilter::Error);
+        assert_eq!(config.get_stream(Level::Info), OutputStream::StdOut);
+    }
+}
@@ -0,0 +1,543 @@
+//! Crash reports for CPU exceptions, with the registers and most recent log lines.
+
+use core::ffi::c_void;
+use core::fmt::{self, Display, Write};
+
+use crate::dump::{DumpHeader, DumpWriter, LineVisitor};
+use crate::module::current_text_segment;
+use crate::sink::create_file;
+use crate::sys::*;
+use crate::{RingBuffer, RtcClock, TimeSource, Timestamp};
+
+/// Conventional names of the MIPS general purpose registers, in order.
+const GPR_NAMES: [&str; 32] = [
+    "zr", "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7",
+    "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "t8", "t9", "k0", "k1", "gp", "sp", "fp", "ra",
+];
+
+/// CPU state at the time of an exception.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RegisterSet {
+    /// Address of the instruction that caused the exception.
+    pub epc: u32,
+
+    /// The COP0 cause register, holding the exception code.
+    pub cause: u32,
+
+    /// The faulting address for address and TLB errors.
+    pub badvaddr: u32,
+
+    /// The COP0 status register.
+    pub status: u32,
+
+    /// General purpose registers `$0` to `$31`.
+    pub gpr: [u32; 32],
+}
+
+impl RegisterSet {
+    /// Description of the exception, decoded from the cause register.
+    pub fn exception(&self) -> &'static str {
+        match (self.cause >> 2) & 0x1f {
+            0 => "Interrupt",
+            1 => "TLB modification",
+            2 => "TLB load/fetch miss",
+            3 => "TLB store miss",
+            4 => "Address error (load/fetch)",
+            5 => "Address error (store)",
+            6 => "Bus error (instruction fetch)",
+            7 => "Bus error (data)",
+            8 => "Syscall",
+            9 => "Breakpoint",
+            10 => "Reserved instruction",
+            11 => "Coprocessor unusable",
+            12 => "Arithmetic overflow",
+            13 => "Trap",
+            _ => "Unknown exception",
+        }
+    }
+}
+
+/// Writes the exception and registers, four general purpose registers per line.
+impl Display for RegisterSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Exception: {}", self.exception())?;
+        writeln!(f, "EPC:      {:#010x}", self.epc)?;
+        writeln!(f, "Cause:    {:#010x}", self.cause)?;
+        writeln!(f, "BadVAddr: {:#010x}", self.badvaddr)?;
+        writeln!(f, "Status:   {:#010x}", self.status)?;
+
+        for row in 0..8 {
+            for column in 0..4 {
+                let index = row * 4 + column;
+                let separator = if column == 3 { "\n" } else { "  " };
+
+                write!(
+                    f,
+                    "{}: {:#010x}{}",
+                    GPR_NAMES[index], self.gpr[index], separator
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Source of the recent log lines included in a crash report.
+trait RecentLines: Sync {
+    /// Lock the lines without waiting and call `f` with a function visiting them, oldest first.
+    ///
+    /// The lines can be visited any number of times while locked, and won't change in between.
+    /// Returns false without calling `f` if the lines are already locked.
+    fn try_lock_lines(&self, f: &mut dyn FnMut(&LineVisitor)) -> bool;
+}
+
+impl<const LINES: usize, const LINE_LEN: usize> RecentLines for RingBuffer<LINES, LINE_LEN> {
+    fn try_lock_lines(&self, f: &mut dyn FnMut(&LineVisitor)) -> bool {
+        self.try_locked(|lines| f(lines)).is_some()
+    }
+}
+
+/// A crash report, made up of the registers followed by the most recent log lines.
+///
+/// This is what [report_crash] writes, and can be displayed to put the report elsewhere.
+///
+/// # Examples
+/// ```
+/// use psp_logger::{CrashReport, RegisterSet, RingBuffer};
+///
+/// static RECENT: RingBuffer<32> = RingBuffer::new();
+///
+/// let regs = RegisterSet {
+///     epc: 0x0880_4a3c,
+///     cause: 4 << 2,
+///     ..Default::default()
+/// };
+///
+/// let report = format!("{}", CrashReport::new(&regs).with_recent_lines(&RECENT));
+/// assert!(report.contains("Address error (load/fetch)"));
+/// ```
+pub struct CrashReport<'a> {
+    regs: &'a RegisterSet,
+    recent: Option<&'a dyn RecentLines>,
+    stack: Option<(u32, &'a [u32])>,
+    app_name: &'a str,
+    build_id: &'a str,
+}
+
+impl<'a> CrashReport<'a> {
+    /// Construct a report of the given registers.
+    pub fn new(regs: &'a RegisterSet) -> Self {
+        CrashReport {
+            regs,
+            recent: None,
+            stack: None,
+            app_name: "",
+            build_id: "",
+        }
+    }
+
+    /// Identify the program that crashed, e.g. with its name and version control revision.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_app_info(mut self, app_name: &'a str, build_id: &'a str) -> Self {
+        self.app_name = app_name;
+        self.build_id = build_id;
+        self
+    }
+
+    /// Include words copied from the stack, starting at address `base`.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_stack(mut self, base: u32, words: &'a [u32]) -> Self {
+        self.stack = Some((base, words));
+        self
+    }
+
+    /// Include the lines held by a [RingBuffer] after the registers.
+    ///
+    /// If the ring buffer is locked when the report is written, for example because the crash
+    /// happened while writing to it, a note is written instead of the lines.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_recent_lines<const LINES: usize, const LINE_LEN: usize>(
+        mut self,
+        recent: &'a RingBuffer<LINES, LINE_LEN>,
+    ) -> Self {
+        self.recent = Some(recent);
+        self
+    }
+}
+
+impl CrashReport<'_> {
+    /// Write the report in the binary [dump](crate::dump) format.
+    fn write_dump(&self, out: &mut dyn FnMut(&[u8])) {
+        let time = match RtcClock.now() {
+            Timestamp::Local(time) => time,
+            Timestamp::SinceBoot(_) => Default::default(),
+        };
+        let header = DumpHeader {
+            app_name: self.app_name,
+            build_id: self.build_id,
+            firmware_version: unsafe { sceKernelDevkitVersion() },
+            time,
+        };
+
+        let mut writer = DumpWriter::new(out, &header);
+        if let Some(text) = current_text_segment() {
+            writer.module(&text);
+        }
+
+        writer.registers(self.regs);
+
+        if let Some((base, words)) = self.stack {
+            writer.stack(base, words);
+        }
+
+        if let Some(recent) = self.recent {
+            recent.try_lock_lines(&mut |lines| writer.log_lines(lines));
+        }
+
+        writer.finish();
+    }
+}
+
+/// Write stack words four to a line, each line starting with the address of its first word.
+fn write_stack(f: &mut dyn Write, words: impl Iterator<Item = (u32, u32)>) -> fmt::Result {
+    for (index, (address, word)) in words.enumerate() {
+        match index % 4 {
+            0 if index > 0 => write!(f, "\n{:#010x}: {:#010x}", address, word)?,
+            0 => write!(f, "{:#010x}: {:#010x}", address, word)?,
+            _ => write!(f, " {:#010x}", word)?,
+        }
+    }
+
+    writeln!(f)
+}
+
+impl Display for CrashReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "=== crash report ===")?;
+        if !self.app_name.is_empty() || !self.build_id.is_empty() {
+            writeln!(f, "App: {} ({})", self.app_name, self.build_id)?;
+        }
+        write!(f, "{}", self.regs)?;
+
+        if let Some((base, words)) = self.stack {
+            writeln!(f, "=== stack ===")?;
+
+            let addresses = (0..).map(|index: u32| base.wrapping_add(index * 4));
+            write_stack(f, addresses.zip(words.iter().copied()))?;
+        }
+
+        let Some(recent) = self.recent else {
+            return Ok(());
+        };
+
+        writeln!(f, "=== recent log lines ===")?;
+
+        let mut result = Ok(());
+        let visited = recent.try_lock_lines(&mut |lines| {
+            lines(&mut |line| {
+                if result.is_ok() {
+                    result = writeln!(f, "{}", line);
+                }
+            })
+        });
+
+        if !visited {
+            writeln!(f, "(log lines unavailable)")?;
+        }
+
+        result
+    }
+}
+
+/// Writer sending everything straight to a file descriptor.
+struct FdWriter(SceUid);
+
+impl FdWriter {
+    fn write_bytes(&mut self, data: &[u8]) {
+        unsafe {
+            sceIoWrite(self.0, data.as_ptr() as *const c_void, data.len());
+        }
+    }
+}
+
+impl Write for FdWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Write a crash report as text to stderr, and as a [dump](crate::dump) to a file.
+///
+/// The report is written directly with syscalls, without formatting into a buffer or going
+/// through the logger, so it is safe to call from an exception handler. It never waits on a
+/// lock, so may leave out the log lines if the ring buffer is in use.
+///
+/// # Arguments
+/// - `report`: The report to write.
+/// - `path`: Path of the dump file, e.g. `ms0:/PSP/GAME/MYAPP/crash.dmp`. It is overwritten if
+///   it exists. If it can't be opened, the report is only written to stderr.
+pub fn report_crash(report: &CrashReport, path: &str) {
+    let _ = write!(FdWriter(unsafe { sceKernelStderr() }), "{}", report);
+
+    if let Ok(file) = create_file(path) {
+        let mut writer = FdWriter(file);
+        report.write_dump(&mut |data| writer.write_bytes(data));
+
+        unsafe {
+            sceIoClose(file);
+        }
+    }
+}
+
+/// Integration with PSPSDK's `pspDebugInstallErrorHandler`.
+#[cfg(all(target_os = "psp", feature = "exception-handler"))]
+mod handler {
+    use super::*;
+
+    /// The start of PSPSDK's `PspDebugRegBlock`, up to the registers used in reports.
+    #[repr(C)]
+    struct PspDebugRegBlock {
+        frame: [u32; 6],
+        r: [u32; 32],
+        status: u32,
+        lo: u32,
+        hi: u32,
+        badvaddr: u32,
+        cause: u32,
+        epc: u32,
+    }
+
+    #[link(name = "pspdebug")]
+    extern "C" {
+        fn pspDebugInstallErrorHandler(
+            handler: Option<unsafe extern "C" fn(regs: *mut PspDebugRegBlock)>,
+        ) -> i32;
+    }
+
+    struct Target {
+        recent: Option<&'static dyn RecentLines>,
+        path: &'static str,
+        app_name: &'static str,
+        build_id: &'static str,
+    }
+
+    /// Number of words copied from the stack into the report.
+    const STACK_WORDS: usize = 64;
+
+    /// User memory, which the stack pointer must be within for the stack to be read.
+    const USER_MEMORY: core::ops::Range<u32> = 0x0880_0000..0x0a00_0000;
+
+    static TARGET: spin::Once<Target> = spin::Once::new();
+
+    unsafe extern "C" fn error_handler(regs: *mut PspDebugRegBlock) {
+        let regs = &*regs;
+        let regs = RegisterSet {
+            epc: regs.epc,
+            cause: regs.cause,
+            badvaddr: regs.badvaddr,
+            status: regs.status,
+            gpr: regs.r,
+        };
+
+        if let Some(target) = TARGET.get() {
+            let mut report =
+                CrashReport::new(&regs).with_app_info(target.app_name, target.build_id);
+            report.recent = target.recent;
+
+            // Only read the stack if the whole range is plausible, to avoid faulting again.
+            let sp = regs.gpr[29];
+            let stack_end = sp.checked_add(STACK_WORDS as u32 * 4);
+            if sp % 4 == 0
+                && USER_MEMORY.contains(&sp)
+                && stack_end.is_some_and(|end| end <= USER_MEMORY.end)
+            {
+                let words = core::slice::from_raw_parts(sp as *const u32, STACK_WORDS);
+                report = report.with_stack(sp, words);
+            }
+
+            report_crash(&report, target.path);
+        }
+
+        loop {
+            sceKernelSleepThread();
+        }
+    }
+
+    /// Install an exception handler that writes a crash report with [report_crash].
+    ///
+    /// The report includes the registers, the top of the crashed thread's stack and the lines
+    /// in `recent`. This uses PSPSDK's `pspDebugInstallErrorHandler`, so requires linking
+    /// `libpspdebug` and running in kernel mode. After writing the report, the crashed thread
+    /// sleeps forever.
+    ///
+    /// Only the first call has any effect. Returns the error code from PSPSDK if the handler
+    /// couldn't be installed.
+    ///
+    /// # Arguments
+    /// - `recent`: Ring buffer holding the most recent log lines, if any.
+    /// - `path`: Path of the dump file written on a crash.
+    /// - `app_name` and `build_id`: Identify the program in the dump, see
+    ///   [CrashReport::with_app_info].
+    pub fn install_exception_handler<const LINES: usize, const LINE_LEN: usize>(
+        recent: Option<&'static RingBuffer<LINES, LINE_LEN>>,
+        path: &'static str,
+        app_name: &'static str,
+        build_id: &'static str,
+    ) -> Result<(), i32> {
+        TARGET.call_once(|| Target {
+            recent: recent.map(|recent| recent as &'static dyn RecentLines),
+            path,
+            app_name,
+            build_id,
+        });
+
+        match unsafe { pspDebugInstallErrorHandler(Some(error_handler)) } {
+            code if code < 0 => Err(code),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(all(target_os = "psp", feature = "exception-handler"))]
+pub use handler::install_exception_handler;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dump::{Dump, Section};
+    use crate::host::{self, STDERR_FD};
+    use crate::{LogSink, OutputStream};
+    use std::string::ToString;
+    use std::vec::Vec;
+
+    fn registers() -> RegisterSet {
+        let mut gpr = [0; 32];
+        for (index, reg) in gpr.iter_mut().enumerate() {
+            *reg = 0x0880_0000 + index as u32;
+        }
+
+        RegisterSet {
+            epc: 0x0880_4a3c,
+            cause: 0x1000_0010,
+            badvaddr: 0x0000_0004,
+            status: 0x2008_8613,
+            gpr,
+        }
+    }
+
+    const REGISTERS: &str = "\
+Exception: Address error (load/fetch)
+EPC:      0x08804a3c
+Cause:    0x10000010
+BadVAddr: 0x00000004
+Status:   0x20088613
+zr: 0x08800000  at: 0x08800001  v0: 0x08800002  v1: 0x08800003
+a0: 0x08800004  a1: 0x08800005  a2: 0x08800006  a3: 0x08800007
+t0: 0x08800008  t1: 0x08800009  t2: 0x0880000a  t3: 0x0880000b
+t4: 0x0880000c  t5: 0x0880000d  t6: 0x0880000e  t7: 0x0880000f
+s0: 0x08800010  s1: 0x08800011  s2: 0x08800012  s3: 0x08800013
+s4: 0x08800014  s5: 0x08800015  s6: 0x08800016  s7: 0x08800017
+t8: 0x08800018  t9: 0x08800019  k0: 0x0880001a  k1: 0x0880001b
+gp: 0x0880001c  sp: 0x0880001d  fp: 0x0880001e  ra: 0x0880001f
+";
+
+    #[test]
+    fn register_dump() {
+        assert_eq!(registers().to_string(), REGISTERS);
+    }
+
+    #[test]
+    fn exception_is_decoded_from_cause() {
+        let exception = |code: u32| {
+            RegisterSet {
+                cause: code << 2,
+                ..Default::default()
+            }
+            .exception()
+        };
+
+        assert_eq!(exception(7), "Bus error (data)");
+        assert_eq!(exception(9), "Breakpoint");
+        assert_eq!(exception(31), "Unknown exception");
+    }
+
+    #[test]
+    fn report_includes_recent_lines() {
+        let recent: RingBuffer<2> = RingBuffer::new();
+        for line in ["one\n", "two\n", "three\n"] {
+            recent.write(OutputStream::StdErr, line);
+        }
+
+        let regs = registers();
+        let report = CrashReport::new(&regs)
+            .with_recent_lines(&recent)
+            .to_string();
+
+        assert_eq!(
+            report,
+            std::format!(
+                "=== crash report ===\n{}=== recent log lines ===\ntwo\nthree\n",
+                REGISTERS
+            )
+        );
+    }
+
+    #[test]
+    fn report_includes_app_info_and_stack() {
+        let regs = registers();
+        let words = [1, 2, 3, 4, 5];
+        let report = CrashReport::new(&regs)
+            .with_app_info("my_game", "1.2.3")
+            .with_stack(0x09ff_f000, &words)
+            .to_string();
+
+        assert!(report.starts_with("=== crash report ===\nApp: my_game (1.2.3)\nException:"));
+        assert!(report.ends_with(
+            "=== stack ===\n\
+             0x09fff000: 0x00000001 0x00000002 0x00000003 0x00000004\n\
+             0x09fff010: 0x00000005\n"
+        ));
+    }
+
+    #[test]
+    fn report_written_to_stderr_and_dump_file() {
+        let recent: RingBuffer<2> = RingBuffer::new();
+        recent.write(OutputStream::StdErr, "last words\n");
+
+        let regs = registers();
+        let report = CrashReport::new(&regs)
+            .with_app_info("my_game", "1.2.3")
+            .with_recent_lines(&recent);
+        let writes = host::capture(|| report_crash(&report, "ms0:/crash.dmp"));
+
+        let output = |to_stderr: bool| -> Vec<u8> {
+            writes
+                .iter()
+                .filter(|write| (write.fd == STDERR_FD) == to_stderr)
+                .flat_map(|write| write.data.iter().copied())
+                .collect()
+        };
+
+        assert_eq!(output(true), report.to_string().as_bytes());
+
+        let file = output(false);
+        let dump = Dump::parse(&file).unwrap();
+        assert_eq!(dump.header.app_name, "my_game");
+        assert_eq!(dump.header.build_id, "1.2.3");
+
+        let sections: Vec<_> = dump.sections().collect::<Result<_, _>>().unwrap();
+        assert!(
+            matches!(sections[0], Section::Module(text) if text.addr == host::MODULE_TEXT_ADDR)
+        );
+        assert!(matches!(sections[1], Section::Registers(parsed) if parsed == regs));
+        match sections[2] {
+            Section::LogLines(lines) => assert_eq!(lines.collect::<Vec<_>>(), ["last words"]),
+            section => panic!("unexpected section {:?}", section),
+        }
+    }
+}
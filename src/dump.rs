@@ -0,0 +1,613 @@
+//! The binary crash dump file format.
+//!
+//! Dumps are written by [report_crash](crate::report_crash), and can be turned back into
+//! readable text on a host with the `psp-crashdump` tool in this repository. All integers are
+//! little-endian.
+//!
+//! A dump starts with a header:
+//!
+//! | Size | Field |
+//! | --- | --- |
+//! | 8 | [DUMP_MAGIC] |
+//! | 2 | Format version, currently [DUMP_VERSION] |
+//! | 4 | Firmware version, from `sceKernelDevkitVersion` |
+//! | 16 | Local time: year, month, day, hour, minute and second as `u16`, then microseconds as `u32` |
+//! | 2 + n | Application name, as a `u16` length followed by UTF-8 |
+//! | 2 + n | Build id, as a `u16` length followed by UTF-8 |
+//!
+//! Then any number of sections, each a `u16` [SectionKind] and a `u32` length followed by that
+//! many bytes, finishing with an [End](SectionKind::End) section. Readers skip sections they
+//! don't recognise, so new kinds can be added without changing the version.
+
+use core::fmt::{self, Display};
+
+use crate::{DateTime, RegisterSet, TextSegment};
+
+/// Bytes at the start of every dump.
+pub const DUMP_MAGIC: [u8; 8] = *b"PSPLDUMP";
+
+/// Version of the dump format written by this crate.
+pub const DUMP_VERSION: u16 = 1;
+
+/// The kinds of section in a dump.
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SectionKind {
+    /// Marks the end of the dump. A dump without one was cut short.
+    End = 0,
+
+    /// EPC, cause, bad vaddr and status, then the 32 general purpose registers, as `u32`s.
+    Registers = 1,
+
+    /// The address of the first stack word as a `u32`, then the stack words as `u32`s.
+    Stack = 2,
+
+    /// Log lines, oldest first, each a `u16` length followed by UTF-8.
+    LogLines = 3,
+
+    /// The address and size of the module's text segment, as `u32`s. See [TextSegment].
+    Module = 4,
+}
+
+/// Details identifying the program and time of a dump.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DumpHeader<'a> {
+    pub app_name: &'a str,
+    pub build_id: &'a str,
+    pub firmware_version: u32,
+    pub time: DateTime,
+}
+
+/// Error returned when a dump can't be parsed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DumpError {
+    /// The data doesn't start with [DUMP_MAGIC].
+    BadMagic,
+
+    /// The dump was written by a newer version of the format, or has an invalid version of 0.
+    UnsupportedVersion(u16),
+
+    /// The dump ends part way through, e.g. because the PSP lost power while writing it.
+    Truncated,
+
+    /// A section's contents don't match its kind.
+    InvalidSection(u16),
+}
+
+/// A parsed dump, borrowing from the raw data.
+///
+/// # Examples
+/// ```
+/// # let data = [];
+/// use psp_logger::dump::{Dump, Section};
+///
+/// if let Ok(dump) = Dump::parse(&data) {
+///     for section in dump.sections() {
+///         if let Ok(Section::Registers(regs)) = section {
+///             println!("{}", regs);
+///         }
+///     }
+/// }
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct Dump<'a> {
+    pub version: u16,
+    pub header: DumpHeader<'a>,
+    sections: &'a [u8],
+}
+
+/// A section of a parsed [Dump].
+#[derive(Copy, Clone, Debug)]
+pub enum Section<'a> {
+    Registers(RegisterSet),
+    Stack(StackWords<'a>),
+    LogLines(LogLines<'a>),
+    Module(TextSegment),
+
+    /// A section this version of the crate doesn't know about.
+    Unknown {
+        kind: u16,
+        data: &'a [u8],
+    },
+}
+
+/// Words copied from the stack, with the address they were copied from.
+#[derive(Copy, Clone, Debug)]
+pub struct StackWords<'a> {
+    pub base: u32,
+    data: &'a [u8],
+}
+
+/// Log lines from a dump, oldest first.
+#[derive(Copy, Clone, Debug)]
+pub struct LogLines<'a> {
+    data: &'a [u8],
+}
+
+/// Iterator over the sections of a [Dump].
+pub struct Sections<'a> {
+    reader: Reader<'a>,
+    done: bool,
+}
+
+/// Cursor over little-endian data.
+#[derive(Copy, Clone, Debug)]
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], DumpError> {
+        if self.data.len() < len {
+            return Err(DumpError::Truncated);
+        }
+
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, DumpError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, DumpError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn str(&mut self) -> Result<&'a str, DumpError> {
+        let len = self.u16()? as usize;
+        Ok(core::str::from_utf8(self.bytes(len)?).unwrap_or("?"))
+    }
+}
+
+impl<'a> Dump<'a> {
+    /// Parse the header of a dump.
+    ///
+    /// Sections are only parsed as they are read with [sections](Self::sections).
+    pub fn parse(data: &'a [u8]) -> Result<Self, DumpError> {
+        let mut reader = Reader { data };
+
+        if reader.bytes(DUMP_MAGIC.len()).ok() != Some(&DUMP_MAGIC[..]) {
+            return Err(DumpError::BadMagic);
+        }
+
+        let version = reader.u16()?;
+        if version == 0 || version > DUMP_VERSION {
+            return Err(DumpError::UnsupportedVersion(version));
+        }
+
+        let firmware_version = reader.u32()?;
+        let time = DateTime {
+            year: reader.u16()?,
+            month: reader.u16()?,
+            day: reader.u16()?,
+            hour: reader.u16()?,
+            minute: reader.u16()?,
+            second: reader.u16()?,
+            microsecond: reader.u32()?,
+        };
+        let app_name = reader.str()?;
+        let build_id = reader.str()?;
+
+        Ok(Dump {
+            version,
+            header: DumpHeader {
+                app_name,
+                build_id,
+                firmware_version,
+                time,
+            },
+            sections: reader.data,
+        })
+    }
+
+    /// The sections of the dump, in the order they were written.
+    ///
+    /// Iteration stops after the first error, which is [DumpError::Truncated] if the dump
+    /// doesn't finish with an [End](SectionKind::End) section.
+    pub fn sections(&self) -> Sections<'a> {
+        Sections {
+            reader: Reader {
+                data: self.sections,
+            },
+            done: false,
+        }
+    }
+}
+
+impl<'a> Sections<'a> {
+    fn read(&mut self) -> Result<Option<Section<'a>>, DumpError> {
+        let kind = self.reader.u16()?;
+        let len = self.reader.u32()? as usize;
+        let mut data = Reader {
+            data: self.reader.bytes(len)?,
+        };
+
+        let section = match kind {
+            kind if kind == SectionKind::End as u16 => return Ok(None),
+            kind if kind == SectionKind::Registers as u16 => {
+                let invalid = |_| DumpError::InvalidSection(kind);
+                let mut regs = RegisterSet {
+                    epc: data.u32().map_err(invalid)?,
+                    cause: data.u32().map_err(invalid)?,
+                    badvaddr: data.u32().map_err(invalid)?,
+                    status: data.u32().map_err(invalid)?,
+                    gpr: [0; 32],
+                };
+                for reg in regs.gpr.iter_mut() {
+                    *reg = data.u32().map_err(invalid)?;
+                }
+
+                Section::Registers(regs)
+            }
+            kind if kind == SectionKind::Stack as u16 => {
+                let base = data.u32().map_err(|_| DumpError::InvalidSection(kind))?;
+                Section::Stack(StackWords {
+                    base,
+                    data: data.data,
+                })
+            }
+            kind if kind == SectionKind::LogLines as u16 => {
+                Section::LogLines(LogLines { data: data.data })
+            }
+            kind if kind == SectionKind::Module as u16 => {
+                let invalid = |_| DumpError::InvalidSection(kind);
+                Section::Module(TextSegment {
+                    addr: data.u32().map_err(invalid)?,
+                    size: data.u32().map_err(invalid)?,
+                })
+            }
+            kind => Section::Unknown {
+                kind,
+                data: data.data,
+            },
+        };
+
+        Ok(Some(section))
+    }
+}
+
+impl<'a> Iterator for Sections<'a> {
+    type Item = Result<Section<'a>, DumpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.read() {
+            Ok(Some(section)) => Some(Ok(section)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+impl StackWords<'_> {
+    /// Each stack word along with its address.
+    pub fn words(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.data.chunks_exact(4).enumerate().map(|(index, word)| {
+            let address = self.base.wrapping_add(index as u32 * 4);
+            (
+                address,
+                u32::from_le_bytes([word[0], word[1], word[2], word[3]]),
+            )
+        })
+    }
+}
+
+impl<'a> Iterator for LogLines<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let mut reader = Reader { data: self.data };
+
+        match reader.str() {
+            Ok(line) => {
+                self.data = reader.data;
+                Some(line)
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+/// Function calling its argument with each of a set of log lines, oldest first.
+pub type LineVisitor<'a> = dyn Fn(&mut dyn FnMut(&str)) + 'a;
+
+/// Writer producing the dump format, one piece at a time.
+///
+/// Sections can be written in any order, and the dump must be finished with
+/// [finish](Self::finish).
+///
+/// # Examples
+/// ```
+/// use psp_logger::dump::{Dump, DumpHeader, DumpWriter};
+///
+/// let mut data = Vec::new();
+/// let mut out = |bytes: &[u8]| data.extend_from_slice(bytes);
+///
+/// let mut writer = DumpWriter::new(&mut out, &DumpHeader::default());
+/// writer.stack(0x09ff_f000, &[0x0880_1234]);
+/// writer.finish();
+///
+/// assert!(Dump::parse(&data).is_ok());
+/// ```
+pub struct DumpWriter<'a> {
+    out: &'a mut dyn FnMut(&[u8]),
+}
+
+impl<'a> DumpWriter<'a> {
+    /// Start a dump, writing its header to `out`.
+    pub fn new(out: &'a mut dyn FnMut(&[u8]), header: &DumpHeader) -> Self {
+        let mut writer = DumpWriter { out };
+
+        writer.bytes(&DUMP_MAGIC);
+        writer.u16(DUMP_VERSION);
+        writer.u32(header.firmware_version);
+        for field in [
+            header.time.year,
+            header.time.month,
+            header.time.day,
+            header.time.hour,
+            header.time.minute,
+            header.time.second,
+        ] {
+            writer.u16(field);
+        }
+        writer.u32(header.time.microsecond);
+        writer.str(header.app_name);
+        writer.str(header.build_id);
+
+        writer
+    }
+
+    /// Write a registers section.
+    pub fn registers(&mut self, regs: &RegisterSet) {
+        self.section(SectionKind::Registers, 36 * 4);
+
+        for value in [regs.epc, regs.cause, regs.badvaddr, regs.status] {
+            self.u32(value);
+        }
+        for &value in &regs.gpr {
+            self.u32(value);
+        }
+    }
+
+    /// Write a stack section, with `base` as the address of the first word.
+    pub fn stack(&mut self, base: u32, words: &[u32]) {
+        self.section(SectionKind::Stack, 4 + words.len() * 4);
+
+        self.u32(base);
+        for &word in words {
+            self.u32(word);
+        }
+    }
+
+    /// Write a module section.
+    pub fn module(&mut self, text: &TextSegment) {
+        self.section(SectionKind::Module, 8);
+
+        self.u32(text.addr);
+        self.u32(text.size);
+    }
+
+    /// Write a log lines section, calling `lines` twice: once to measure and once to write.
+    ///
+    /// Lines longer than `u16::MAX` bytes are cut short.
+    pub fn log_lines(&mut self, lines: &LineVisitor) {
+        let mut len = 0;
+        lines(&mut |line| len += 2 + line_len(line));
+        self.section(SectionKind::LogLines, len);
+
+        lines(&mut |line| {
+            let line = &line.as_bytes()[..line_len(line)];
+            self.u16(line.len() as u16);
+            self.bytes(line);
+        });
+    }
+
+    /// Finish the dump with an end section.
+    pub fn finish(mut self) {
+        self.section(SectionKind::End, 0);
+    }
+
+    fn section(&mut self, kind: SectionKind, len: usize) {
+        self.u16(kind as u16);
+        self.u32(len as u32);
+    }
+
+    fn bytes(&mut self, data: &[u8]) {
+        (self.out)(data);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn str(&mut self, s: &str) {
+        let s = &s.as_bytes()[..line_len(s)];
+        self.u16(s.len() as u16);
+        self.bytes(s);
+    }
+}
+
+/// Length of `line` once cut down to fit a `u16` length, without splitting a character.
+fn line_len(line: &str) -> usize {
+    let mut len = line.len().min(u16::MAX as usize);
+    while !line.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    len
+}
+
+impl Display for DumpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DumpError::BadMagic => write!(f, "not a crash dump"),
+            DumpError::UnsupportedVersion(version) => {
+                write!(f, "unsupported dump version {}", version)
+            }
+            DumpError::Truncated => write!(f, "dump is truncated"),
+            DumpError::InvalidSection(kind) => write!(f, "invalid section of kind {}", kind),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    fn header() -> DumpHeader<'static> {
+        DumpHeader {
+            app_name: "my_game",
+            build_id: "1.2.3-abcdef",
+            firmware_version: 0x0606_0010,
+            time: DateTime {
+                year: 2024,
+                month: 5,
+                day: 17,
+                hour: 21,
+                minute: 3,
+                second: 9,
+                microsecond: 123_456,
+            },
+        }
+    }
+
+    fn write(f: impl FnOnce(&mut DumpWriter)) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut out = |bytes: &[u8]| data.extend_from_slice(bytes);
+
+        let mut writer = DumpWriter::new(&mut out, &header());
+        f(&mut writer);
+        writer.finish();
+
+        data
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut regs = RegisterSet {
+            epc: 0x0880_4a3c,
+            cause: 7 << 2,
+            badvaddr: 4,
+            status: 0x2008_8613,
+            gpr: [0; 32],
+        };
+        regs.gpr[31] = 0x0880_1234;
+
+        let data = write(|writer| {
+            writer.registers(&regs);
+            writer.module(&TextSegment {
+                addr: 0x0880_4000,
+                size: 0x1000,
+            });
+            writer.stack(0x09ff_f000, &[1, 0x0880_1234]);
+            writer.log_lines(&|f| {
+                f("one");
+                f("");
+                f("three");
+            });
+        });
+
+        let dump = Dump::parse(&data).unwrap();
+        assert_eq!(dump.version, DUMP_VERSION);
+        assert_eq!(dump.header, header());
+
+        let sections: Vec<_> = dump.sections().collect::<Result<_, _>>().unwrap();
+        assert_eq!(sections.len(), 4);
+
+        match sections[0] {
+            Section::Registers(parsed) => assert_eq!(parsed, regs),
+            section => panic!("unexpected section {:?}", section),
+        }
+        match sections[1] {
+            Section::Module(text) => assert_eq!(
+                text,
+                TextSegment {
+                    addr: 0x0880_4000,
+                    size: 0x1000
+                }
+            ),
+            section => panic!("unexpected section {:?}", section),
+        }
+        match sections[2] {
+            Section::Stack(stack) => assert_eq!(
+                stack.words().collect::<Vec<_>>(),
+                [(0x09ff_f000, 1), (0x09ff_f004, 0x0880_1234)]
+            ),
+            section => panic!("unexpected section {:?}", section),
+        }
+        match sections[3] {
+            Section::LogLines(lines) => {
+                assert_eq!(lines.collect::<Vec<_>>(), ["one", "", "three"])
+            }
+            section => panic!("unexpected section {:?}", section),
+        }
+    }
+
+    #[test]
+    fn unknown_sections_are_skipped_over() {
+        let mut data = write(|_| {});
+        let end = data.split_off(data.len() - 6);
+        data.extend_from_slice(&[0x34, 0x12, 2, 0, 0, 0, 0xaa, 0xbb]);
+        data.extend_from_slice(&end);
+
+        let dump = Dump::parse(&data).unwrap();
+        let sections: Vec<_> = dump.sections().collect();
+
+        assert_eq!(sections.len(), 1);
+        match sections[0] {
+            Ok(Section::Unknown { kind, data }) => {
+                assert_eq!(kind, 0x1234);
+                assert_eq!(data, [0xaa, 0xbb]);
+            }
+            section => panic!("unexpected section {:?}", section),
+        }
+    }
+
+    #[test]
+    fn truncated_dump() {
+        let data = write(|writer| writer.stack(0, &[1, 2, 3]));
+        let dump = Dump::parse(&data[..data.len() - 10]).unwrap();
+
+        let sections: Vec<_> = dump.sections().collect();
+        assert!(matches!(sections[..], [Err(DumpError::Truncated)]));
+
+        assert_eq!(Dump::parse(&data[..12]).unwrap_err(), DumpError::Truncated);
+    }
+
+    #[test]
+    fn header_errors() {
+        assert_eq!(Dump::parse(b"not a dump").unwrap_err(), DumpError::BadMagic);
+
+        let mut data = write(|_| {});
+        data[8] = 0xff;
+        assert_eq!(
+            Dump::parse(&data).unwrap_err(),
+            DumpError::UnsupportedVersion(0xff)
+        );
+
+        data[8] = 0;
+        assert_eq!(
+            Dump::parse(&data).unwrap_err(),
+            DumpError::UnsupportedVersion(0)
+        );
+    }
+}
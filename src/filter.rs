@@ -0,0 +1,355 @@
+//! Per-target level filtering, configured with env_logger style directive strings.
+
+use core::fmt::{self, Display};
+use core::str::FromStr;
+
+use log::LevelFilter;
+
+use crate::sink::read_file;
+
+/// Maximum number of per-target directives in a [Directives].
+pub const MAX_DIRECTIVES: usize = 16;
+
+/// Maximum length in bytes of a directive's target.
+pub const MAX_TARGET_LEN: usize = 64;
+
+/// Largest directive file that [Directives::from_file] can read, in bytes.
+const MAX_FILE_LEN: usize = 1024;
+
+/// Level filters for individual log targets.
+///
+/// Directives are written as a comma separated list, in the same style as `env_logger`:
+/// - `warn` sets the level for any target without a more specific directive.
+/// - `my_game::render=trace` sets the level for `my_game::render` and its submodules.
+/// - `my_game::audio` on its own enables every level for that target.
+///
+/// A record uses the directive with the longest target matching its own. Targets match whole
+/// module path segments, so `my_game::render` matches `my_game::render::mesh` but not
+/// `my_game::renderer`.
+///
+/// Directives are stored inline, so they can be parsed at runtime without allocating.
+///
+/// # Examples
+/// ```
+/// use psp_logger::{Directives, PspLoggerConfig};
+///
+/// let directives = Directives::parse("warn,my_game::render=trace,my_game::audio=off").unwrap();
+///
+/// let config = PspLoggerConfig::new(log::LevelFilter::Info).with_directives(directives);
+/// ```
+///
+/// There is no environment on the PSP, but directives can be baked in when building instead:
+/// ```ignore
+/// let directives = Directives::parse(env!("PSP_LOG")).unwrap();
+/// ```
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Directives {
+    default: Option<LevelFilter>,
+    targets: [Option<Directive>; MAX_DIRECTIVES],
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Directive {
+    target: [u8; MAX_TARGET_LEN],
+    len: usize,
+    level: LevelFilter,
+}
+
+/// Error returned when directives can't be parsed or read.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DirectiveError {
+    /// The directive at this index (counting from 0) has a level that isn't recognised.
+    InvalidLevel(usize),
+
+    /// The directive at this index has a target longer than [MAX_TARGET_LEN].
+    TargetTooLong(usize),
+
+    /// There are more than [MAX_DIRECTIVES] per-target directives.
+    TooManyDirectives,
+
+    /// The directive file couldn't be read, with the PSP error code.
+    Io(i32),
+
+    /// The directive file isn't valid UTF-8.
+    InvalidUtf8,
+}
+
+impl Directive {
+    fn target(&self) -> &str {
+        // Only ever filled from a `&str`.
+        core::str::from_utf8(&self.target[..self.len]).unwrap_or("")
+    }
+
+    fn matches(&self, target: &str) -> bool {
+        let prefix = self.target();
+
+        match target.strip_prefix(prefix) {
+            Some(rest) => rest.is_empty() || rest.starts_with("::"),
+            None => false,
+        }
+    }
+}
+
+impl Directives {
+    /// Construct an empty set of directives, which doesn't affect filtering at all.
+    pub const fn new() -> Self {
+        Directives {
+            default: None,
+            targets: [None; MAX_DIRECTIVES],
+        }
+    }
+
+    /// Parse a comma separated directive string, such as `warn,my_game::render=trace`.
+    ///
+    /// Whitespace around directives is ignored, as are empty directives.
+    pub fn parse(s: &str) -> Result<Self, DirectiveError> {
+        let mut directives = Self::new();
+        let mut count = 0;
+
+        for (index, directive) in s.split(',').map(str::trim).enumerate() {
+            if directive.is_empty() {
+                continue;
+            }
+
+            let (target, level) = match directive.split_once('=') {
+                Some((target, level)) => {
+                    let level = LevelFilter::from_str(level.trim())
+                        .map_err(|_| DirectiveError::InvalidLevel(index))?;
+                    (Some(target.trim()), level)
+                }
+                // A lone directive is either a level, or a target with every level enabled.
+                None => match LevelFilter::from_str(directive) {
+                    Ok(level) => (None, level),
+                    Err(_) => (Some(directive), LevelFilter::Trace),
+                },
+            };
+
+            let Some(target) = target else {
+                directives.default = Some(level);
+                continue;
+            };
+
+            if target.len() > MAX_TARGET_LEN {
+                return Err(DirectiveError::TargetTooLong(index));
+            }
+
+            // A later directive for the same target replaces the earlier one.
+            let slot = match directives
+                .targets
+                .iter()
+                .position(|d| d.is_some_and(|d| d.target() == target))
+            {
+                Some(slot) => slot,
+                None if count < MAX_DIRECTIVES => {
+                    count += 1;
+                    count - 1
+                }
+                None => return Err(DirectiveError::TooManyDirectives),
+            };
+
+            let mut directive = Directive {
+                target: [0; MAX_TARGET_LEN],
+                len: target.len(),
+                level,
+            };
+            directive.target[..target.len()].copy_from_slice(target.as_bytes());
+            directives.targets[slot] = Some(directive);
+        }
+
+        Ok(directives)
+    }
+
+    /// Read and parse directives from a file, e.g. on the memory stick.
+    ///
+    /// The file holds a directive string as accepted by [parse](Self::parse), and may be split
+    /// across multiple lines. It must be smaller than 1KiB.
+    pub fn from_file(path: &str) -> Result<Self, DirectiveError> {
+        let mut buf = [0; MAX_FILE_LEN];
+        let len = read_file(path, &mut buf).map_err(DirectiveError::Io)?;
+        let text = core::str::from_utf8(&buf[..len]).map_err(|_| DirectiveError::InvalidUtf8)?;
+
+        let mut directives = Self::new();
+        for line in text.lines() {
+            directives.merge(&Self::parse(line)?)?;
+        }
+
+        Ok(directives)
+    }
+
+    /// The level filter set by a directive without a target, if there is one.
+    pub fn default_level(&self) -> Option<LevelFilter> {
+        self.default
+    }
+
+    /// The level filter for the directive with the longest target matching `target`, if any.
+    pub fn level_for(&self, target: &str) -> Option<LevelFilter> {
+        self.targets
+            .iter()
+            .flatten()
+            .filter(|directive| directive.matches(target))
+            .max_by_key(|directive| directive.len)
+            .map(|directive| directive.level)
+    }
+
+    /// The most verbose level filter used by any directive.
+    pub fn max_level(&self) -> Option<LevelFilter> {
+        self.targets
+            .iter()
+            .flatten()
+            .map(|directive| directive.level)
+            .chain(self.default)
+            .max()
+    }
+
+    /// Remove the directive without a target, if there is one.
+    pub(crate) fn clear_default_level(&mut self) {
+        self.default = None;
+    }
+
+    /// Whether there are no directives at all.
+    pub fn is_empty(&self) -> bool {
+        self.default.is_none() && self.targets.iter().all(Option::is_none)
+    }
+
+    /// Add the directives in `other`, which take precedence over existing ones.
+    fn merge(&mut self, other: &Directives) -> Result<(), DirectiveError> {
+        if other.default.is_some() {
+            self.default = other.default;
+        }
+
+        for directive in other.targets.iter().flatten() {
+            let slot = self
+                .targets
+                .iter()
+                .position(|d| d.is_some_and(|d| d.target() == directive.target()))
+                .or_else(|| self.targets.iter().position(Option::is_none))
+                .ok_or(DirectiveError::TooManyDirectives)?;
+
+            self.targets[slot] = Some(*directive);
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for DirectiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DirectiveError::InvalidLevel(index) => {
+                write!(f, "directive {} has an invalid level", index)
+            }
+            DirectiveError::TargetTooLong(index) => write!(
+                f,
+                "directive {} has a target longer than {} bytes",
+                index, MAX_TARGET_LEN
+            ),
+            DirectiveError::TooManyDirectives => {
+                write!(f, "more than {} directives", MAX_DIRECTIVES)
+            }
+            DirectiveError::Io(code) => write!(f, "could not read directives: {:#010x}", code),
+            DirectiveError::InvalidUtf8 => write!(f, "directive file is not valid UTF-8"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host;
+
+    #[test]
+    fn bare_level_sets_default() {
+        let directives = Directives::parse("warn").unwrap();
+
+        assert_eq!(directives.default_level(), Some(LevelFilter::Warn));
+        assert_eq!(directives.level_for("anything"), None);
+    }
+
+    #[test]
+    fn longest_matching_target_wins() {
+        let directives =
+            Directives::parse("warn, my_game=info ,my_game::render=trace,my_game::audio=off")
+                .unwrap();
+
+        assert_eq!(directives.level_for("my_game"), Some(LevelFilter::Info));
+        assert_eq!(directives.level_for("my_game::ui"), Some(LevelFilter::Info));
+        assert_eq!(
+            directives.level_for("my_game::render::mesh"),
+            Some(LevelFilter::Trace)
+        );
+        assert_eq!(
+            directives.level_for("my_game::audio"),
+            Some(LevelFilter::Off)
+        );
+        assert_eq!(directives.level_for("other"), None);
+        assert_eq!(directives.max_level(), Some(LevelFilter::Trace));
+    }
+
+    #[test]
+    fn targets_match_whole_segments() {
+        let directives = Directives::parse("my_game::render=trace").unwrap();
+
+        assert_eq!(directives.level_for("my_game::renderer"), None);
+    }
+
+    #[test]
+    fn lone_target_enables_everything() {
+        let directives = Directives::parse("my_game::audio").unwrap();
+
+        assert_eq!(
+            directives.level_for("my_game::audio"),
+            Some(LevelFilter::Trace)
+        );
+    }
+
+    #[test]
+    fn later_directives_replace_earlier_ones() {
+        let directives = Directives::parse("a=info,a=debug,,").unwrap();
+
+        assert_eq!(directives.level_for("a"), Some(LevelFilter::Debug));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Directives::parse("info,a=loud").unwrap_err(),
+            DirectiveError::InvalidLevel(1)
+        );
+
+        let long_target = "x".repeat(MAX_TARGET_LEN + 1);
+        assert_eq!(
+            Directives::parse(&long_target).unwrap_err(),
+            DirectiveError::TargetTooLong(0)
+        );
+
+        let too_many: std::vec::Vec<_> = (0..=MAX_DIRECTIVES)
+            .map(|i| std::format!("t{}", i))
+            .collect();
+        assert_eq!(
+            Directives::parse(&too_many.join(",")).unwrap_err(),
+            DirectiveError::TooManyDirectives
+        );
+    }
+
+    #[test]
+    fn read_from_file() {
+        host::add_file(
+            "ms0:/directives.txt",
+            b"info\nmy_game::render=trace,\nnet=off\n",
+        );
+
+        let directives = Directives::from_file("ms0:/directives.txt").unwrap();
+
+        assert_eq!(directives.default_level(), Some(LevelFilter::Info));
+        assert_eq!(
+            directives.level_for("my_game::render"),
+            Some(LevelFilter::Trace)
+        );
+        assert_eq!(directives.level_for("net"), Some(LevelFilter::Off));
+
+        assert_eq!(
+            Directives::from_file("ms0:/missing.txt").unwrap_err(),
+            DirectiveError::Io(host::FILE_NOT_FOUND)
+        );
+    }
+}
@@ -0,0 +1,473 @@
+//! Formatting of records into lines according to a template.
+
+use core::fmt::{self, Display, Write};
+
+use log::Record;
+
+use crate::thread;
+use crate::TimeSource;
+
+/// Template used when none is configured, matching the logger's original output.
+pub(crate) const DEFAULT_FORMAT: &str = "{msg}";
+
+/// Maximum length in bytes of a formatted line, including its trailing newline.
+///
+/// Lines are formatted into a buffer of this size on the stack rather than on the heap. Longer
+/// lines are cut short, with `...` marking where the truncation happened.
+pub const MAX_LINE_LEN: usize = 512;
+
+/// Maximum length in bytes of a template that isn't `'static`, such as one read from a file.
+pub const MAX_TEMPLATE_LEN: usize = 128;
+
+/// Marker written at the end of a truncated line.
+const TRUNCATION_MARKER: &str = "...";
+
+/// A format template, either borrowed for the lifetime of the program or stored inline.
+#[derive(Copy, Clone)]
+pub(crate) enum Template {
+    Static(&'static str),
+    Inline {
+        buf: [u8; MAX_TEMPLATE_LEN],
+        len: usize,
+    },
+}
+
+impl Template {
+    /// Copy `s` into an inline template, if it fits.
+    pub(crate) fn copy_from(s: &str) -> Option<Self> {
+        if s.len() > MAX_TEMPLATE_LEN {
+            return None;
+        }
+
+        let mut buf = [0; MAX_TEMPLATE_LEN];
+        buf[..s.len()].copy_from_slice(s.as_bytes());
+
+        Some(Template::Inline { buf, len: s.len() })
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        match self {
+            Template::Static(s) => s,
+            // Only ever filled from a `&str`.
+            Template::Inline { buf, len } => core::str::from_utf8(&buf[..*len]).unwrap_or(""),
+        }
+    }
+}
+
+/// Fixed-size buffer that a single line is formatted into.
+///
+/// One byte is always kept free for the trailing newline added by [finish](Self::finish).
+/// Writes that don't fit are truncated on a character boundary and followed by
+/// [TRUNCATION_MARKER], after which the buffer refuses further writes.
+pub(crate) struct LineBuffer<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    truncated: bool,
+}
+
+impl<const N: usize> LineBuffer<N> {
+    pub(crate) fn new() -> Self {
+        LineBuffer {
+            buf: [0; N],
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    /// Terminate the line with a newline and return it.
+    pub(crate) fn finish(&mut self) -> &str {
+        self.buf[self.len] = b'\n';
+        self.len += 1;
+
+        self.as_str()
+    }
+
+    fn as_str(&self) -> &str {
+        // Only ever filled with whole characters, so this is always valid UTF-8.
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+
+    fn push(&mut self, s: &str) {
+        self.buf[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+        self.len += s.len();
+    }
+}
+
+impl<const N: usize> Write for LineBuffer<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.truncated {
+            return Err(fmt::Error);
+        }
+
+        if self.len + s.len() < N {
+            self.push(s);
+            return Ok(());
+        }
+
+        // Cut back to leave room for the marker, without splitting a character.
+        let limit = N - 1 - TRUNCATION_MARKER.len();
+
+        if self.len > limit {
+            self.len = limit;
+            while self.len > 0 && self.buf[self.len] & 0xC0 == 0x80 {
+                self.len -= 1;
+            }
+        } else {
+            let mut end = limit - self.len;
+            while !s.is_char_boundary(end) {
+                end -= 1;
+            }
+            self.push(&s[..end]);
+        }
+
+        self.push(TRUNCATION_MARKER);
+        self.truncated = true;
+
+        Err(fmt::Error)
+    }
+}
+
+/// A record rendered according to a format template.
+///
+/// See [PspLoggerConfig::with_format](crate::PspLoggerConfig::with_format) for the template
+/// syntax.
+pub(crate) struct FormattedRecord<'a> {
+    template: &'a str,
+    record: &'a Record<'a>,
+    time: &'a dyn TimeSource,
+    /// Whether the current thread can be looked up.
+    thread: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Field {
+    Time,
+    Level,
+    Target,
+    Module,
+    File,
+    Line,
+    ThreadId,
+    ThreadName,
+    Msg,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Placeholder {
+    field: Field,
+    align: Align,
+    width: usize,
+}
+
+impl Placeholder {
+    /// Parse the contents of a `{...}` placeholder, e.g. `level:>5`.
+    fn parse(s: &str) -> Option<Self> {
+        let (name, spec) = s.split_once(':').unwrap_or((s, ""));
+
+        let field = match name {
+            "time" => Field::Time,
+            "level" => Field::Level,
+            "target" => Field::Target,
+            "module" => Field::Module,
+            "file" => Field::File,
+            "line" => Field::Line,
+            "thread_id" => Field::ThreadId,
+            "thread" => Field::ThreadName,
+            "msg" => Field::Msg,
+            _ => return None,
+        };
+
+        let (align, width) = match spec.as_bytes().first() {
+            Some(b'<') => (Align::Left, &spec[1..]),
+            Some(b'>') => (Align::Right, &spec[1..]),
+            Some(b'^') => (Align::Center, &spec[1..]),
+            _ => (Align::Left, spec),
+        };
+
+        let width = if width.is_empty() {
+            0
+        } else {
+            width.parse().ok()?
+        };
+
+        Some(Placeholder {
+            field,
+            align,
+            width,
+        })
+    }
+}
+
+/// Write `value` padded to `width` characters.
+///
+/// To be measured, the value is formatted once into a buffer, which is then written out, so that
+/// it isn't formatted twice. Values longer than a line are truncated.
+fn write_padded(
+    f: &mut fmt::Formatter<'_>,
+    value: &dyn Display,
+    align: Align,
+    width: usize,
+) -> fmt::Result {
+    if width == 0 {
+        return value.fmt(f);
+    }
+
+    let mut buf = LineBuffer::<MAX_LINE_LEN>::new();
+    // Formatting stops early once the buffer is full, so the error is expected.
+    let _ = write!(buf, "{}", value);
+    let value = buf.as_str();
+
+    let padding = width.saturating_sub(value.chars().count());
+    let (before, after) = match align {
+        Align::Left => (0, padding),
+        Align::Right => (padding, 0),
+        Align::Center => (padding / 2, padding - padding / 2),
+    };
+
+    for _ in 0..before {
+        f.write_char(' ')?;
+    }
+    f.write_str(value)?;
+    for _ in 0..after {
+        f.write_char(' ')?;
+    }
+
+    Ok(())
+}
+
+impl<'a> FormattedRecord<'a> {
+    pub(crate) fn new(template: &'a str, record: &'a Record<'a>, time: &'a dyn TimeSource) -> Self {
+        FormattedRecord {
+            template,
+            record,
+            time,
+            thread: true,
+        }
+    }
+
+    /// Render `{thread_id}` and `{thread}` as `?`, for records logged where the current thread
+    /// can't be looked up, such as in interrupt context.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub(crate) fn without_thread(mut self) -> Self {
+        self.thread = false;
+        self
+    }
+
+    fn write_placeholder(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        placeholder: Placeholder,
+    ) -> fmt::Result {
+        let record = self.record;
+        let write = |f: &mut fmt::Formatter<'_>, value: &dyn Display| {
+            write_padded(f, value, placeholder.align, placeholder.width)
+        };
+
+        match placeholder.field {
+            Field::Time => write(f, &self.time.now()),
+            Field::Level => write(f, &record.level()),
+            Field::Target => write(f, &record.target()),
+            Field::Module => write(f, &record.module_path().unwrap_or("?")),
+            Field::File => write(f, &record.file().unwrap_or("?")),
+            Field::Line => match record.line() {
+                Some(line) => write(f, &line),
+                None => write(f, &"?"),
+            },
+            Field::ThreadId | Field::ThreadName if !self.thread => write(f, &"?"),
+            Field::ThreadId => write(f, &format_args!("{:#010x}", thread::current_id())),
+            Field::ThreadName => thread::with_current_name(|name| write(f, &name)),
+            Field::Msg => write(f, record.args()),
+        }
+    }
+}
+
+impl Display for FormattedRecord<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rest = self.template;
+
+        while let Some(start) = rest.find(['{', '}']) {
+            f.write_str(&rest[..start])?;
+            let tail = &rest[start..];
+
+            if tail.starts_with("{{") || tail.starts_with("}}") {
+                f.write_str(&tail[..1])?;
+                rest = &tail[2..];
+            } else if let Some(after) = tail.strip_prefix('}') {
+                f.write_str("}")?;
+                rest = after;
+            } else if let Some(end) = tail.find('}') {
+                match Placeholder::parse(&tail[1..end]) {
+                    Some(placeholder) => self.write_placeholder(f, placeholder)?,
+                    // Unknown placeholders are written out as-is.
+                    None => f.write_str(&tail[..=end])?,
+                }
+                rest = &tail[end + 1..];
+            } else {
+                f.write_str(tail)?;
+                rest = "";
+            }
+        }
+
+        f.write_str(rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Timestamp;
+    use log::Level;
+    use std::string::{String, ToString};
+
+    struct FixedClock;
+
+    impl TimeSource for FixedClock {
+        fn now(&self) -> Timestamp {
+            Timestamp::SinceBoot(3_141_592)
+        }
+    }
+
+    fn render(template: &str, level: Level, msg: &str) -> String {
+        FormattedRecord::new(
+            template,
+            &Record::builder()
+                .level(level)
+                .target("game::render")
+                .module_path(Some("game::render::mesh"))
+                .file(Some("src/render/mesh.rs"))
+                .line(Some(42))
+                .args(format_args!("{}", msg))
+                .build(),
+            &FixedClock,
+        )
+        .to_string()
+    }
+
+    #[test]
+    fn default_format_is_just_the_message() {
+        assert_eq!(render(DEFAULT_FORMAT, Level::Info, "hello"), "hello");
+    }
+
+    #[test]
+    fn all_fields() {
+        assert_eq!(
+            render(
+                "{level} [{target}] {module} {file}:{line} {msg}",
+                Level::Warn,
+                "hi"
+            ),
+            "WARN [game::render] game::render::mesh src/render/mesh.rs:42 hi"
+        );
+    }
+
+    #[test]
+    fn widths_and_alignment() {
+        assert_eq!(render("{level:5}|", Level::Info, ""), "INFO |");
+        assert_eq!(render("{level:>5}|", Level::Info, ""), " INFO|");
+        assert_eq!(render("{level:^7}|", Level::Info, ""), " INFO  |");
+        assert_eq!(
+            render("{msg:3}|", Level::Info, "long message"),
+            "long message|"
+        );
+    }
+
+    /// Formats as `counted`, counting how often it is formatted.
+    struct Counted(core::cell::Cell<usize>);
+
+    impl Display for Counted {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.0.set(self.0.get() + 1);
+            f.write_str("counted")
+        }
+    }
+
+    #[test]
+    fn padded_message_is_formatted_once() {
+        let counted = Counted(Default::default());
+        let line = FormattedRecord::new(
+            "{msg:>9}|",
+            &Record::builder()
+                .level(Level::Info)
+                .args(format_args!("{}", counted))
+                .build(),
+            &FixedClock,
+        )
+        .to_string();
+
+        assert_eq!(line, "  counted|");
+        assert_eq!(counted.0.get(), 1);
+    }
+
+    #[test]
+    fn missing_location_is_shown_as_question_mark() {
+        let line = FormattedRecord::new(
+            "{module} {file}:{line}",
+            &Record::builder().args(format_args!("")).build(),
+            &FixedClock,
+        )
+        .to_string();
+
+        assert_eq!(line, "? ?:?");
+    }
+
+    #[test]
+    fn braces_can_be_escaped() {
+        assert_eq!(render("{{{msg}}}", Level::Info, "x"), "{x}");
+    }
+
+    #[test]
+    fn unknown_and_malformed_placeholders_are_kept() {
+        assert_eq!(
+            render("{nope} {level:x} {msg", Level::Info, "x"),
+            "{nope} {level:x} {msg"
+        );
+    }
+
+    #[test]
+    fn thread_id_and_name() {
+        let line = crate::host::with_thread(0x0123abcd, "streaming", || {
+            render("{thread_id} {thread:>10}|", Level::Info, "")
+        });
+
+        assert_eq!(line, "0x0123abcd  streaming|");
+    }
+
+    fn line<const N: usize>(parts: &[&str]) -> String {
+        let mut buf = LineBuffer::<N>::new();
+        for part in parts {
+            let _ = buf.write_str(part);
+        }
+        buf.finish().to_string()
+    }
+
+    #[test]
+    fn line_buffer_fits_exactly() {
+        assert_eq!(line::<8>(&["abc", "defg"]), "abcdefg\n");
+    }
+
+    #[test]
+    fn line_buffer_truncates_with_marker() {
+        assert_eq!(line::<8>(&["abc", "defgh"]), "abcd...\n");
+        assert_eq!(line::<8>(&["abcdefg", "h"]), "abcd...\n");
+        assert_eq!(line::<8>(&["abc", "defgh", "ij"]), "abcd...\n");
+    }
+
+    #[test]
+    fn line_buffer_truncates_on_char_boundary() {
+        assert_eq!(line::<8>(&["abc\u{e9}\u{e9}x"]), "abc...\n");
+        assert_eq!(line::<8>(&["abc\u{e9}\u{e9}", "x"]), "abc...\n");
+    }
+
+    #[test]
+    fn time_comes_from_time_source() {
+        assert_eq!(render("{time:>10}|{msg}", Level::Info, "x"), "  3.141592|x");
+    }
+}
@@ -0,0 +1,437 @@
+//! Host backend standing in for the PSP syscalls.
+//!
+//! This module only exists when building for something other than the PSP. Instead of
+//! talking to the kernel, the replacement syscalls record everything written to them so
+//! that tests can assert on the exact output of the logger.
+//!
+//! # Examples
+//! ```
+//! use psp_logger::host;
+//!
+//! let writes = host::capture(|| {
+//!     // Anything logged here is recorded rather than printed.
+//! });
+//!
+//! assert!(writes.is_empty());
+//! ```
+#![allow(non_snake_case)]
+
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::ops::BitOr;
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicI64, Ordering};
+
+use crate::DateTime;
+
+/// File descriptor returned by the host version of `sceKernelStdout`.
+pub const STDOUT_FD: SceUid = SceUid(1);
+
+/// File descriptor returned by the host version of `sceKernelStderr`.
+pub const STDERR_FD: SceUid = SceUid(2);
+
+/// Stand-in for `psp::sys::SceUid`.
+#[repr(transparent)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SceUid(pub i32);
+
+/// Stand-in for `psp::sys::IoOpenFlags`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IoOpenFlags(i32);
+
+impl IoOpenFlags {
+    pub const RD_ONLY: Self = Self(0x0001);
+    pub const WR_ONLY: Self = Self(0x0002);
+    pub const RD_WR: Self = Self(0x0003);
+    pub const APPEND: Self = Self(0x0100);
+    pub const CREAT: Self = Self(0x0200);
+    pub const TRUNC: Self = Self(0x0400);
+
+    pub const fn bits(&self) -> i32 {
+        self.0
+    }
+}
+
+impl BitOr for IoOpenFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Stand-in for `psp::sys::IoWhence`.
+#[repr(i32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IoWhence {
+    Set = 0,
+    Cur = 1,
+    End = 2,
+}
+
+/// Stand-in for `psp::sys::ScePspDateTime`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ScePspDateTime {
+    pub year: u16,
+    pub month: u16,
+    pub day: u16,
+    pub hour: u16,
+    pub minutes: u16,
+    pub seconds: u16,
+    pub microseconds: u32,
+}
+
+/// Stand-in for `psp::sys::SceKernelThreadInfo`, holding only the fields the logger uses.
+#[repr(C)]
+pub(crate) struct SceKernelThreadInfo {
+    pub size: usize,
+    pub name: [u8; 32],
+    pub stack: *mut c_void,
+    pub stack_size: i32,
+}
+
+/// Stand-in for `psp::sys::SceKernelModuleInfo`.
+#[repr(C)]
+pub(crate) struct SceKernelModuleInfo {
+    pub size: usize,
+    pub n_segment: u8,
+    pub reserved: [u8; 3],
+    pub segment_addr: [i32; 4],
+    pub segment_size: [i32; 4],
+    pub entry_addr: u32,
+    pub gp_value: u32,
+    pub text_addr: u32,
+    pub text_size: u32,
+    pub data_size: u32,
+    pub bss_size: u32,
+    pub attribute: u16,
+    pub version: [u8; 2],
+    pub name: [u8; 28],
+}
+
+/// Stand-in for `psp::sys::ThreadAttributes`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ThreadAttributes(u32);
+
+impl ThreadAttributes {
+    pub const VFPU: Self = Self(0x0000_4000);
+    pub const USER: Self = Self(0x8000_0000);
+}
+
+/// Stand-in for `psp::sys::SceKernelThreadOptParam`.
+#[repr(C)]
+pub(crate) struct SceKernelThreadOptParam {
+    pub size: usize,
+    pub stack_mpid: SceUid,
+}
+
+/// Stand-in for `psp::sys::SceKernelThreadEntry`.
+pub(crate) type SceKernelThreadEntry = unsafe extern "C" fn(args: usize, argp: *mut c_void) -> i32;
+
+/// A single call to `sceIoWrite`, as recorded by the host backend.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordedWrite {
+    pub fd: SceUid,
+    pub data: Vec<u8>,
+}
+
+static WRITES: spin::Mutex<Vec<RecordedWrite>> = spin::Mutex::new(Vec::new());
+static CAPTURE_LOCK: spin::Mutex<()> = spin::Mutex::new(());
+static NEXT_FD: AtomicI32 = AtomicI32::new(3);
+static FILES: spin::Mutex<Vec<(Vec<u8>, Vec<u8>)>> = spin::Mutex::new(Vec::new());
+static OPEN_FILES: spin::Mutex<Vec<(SceUid, Vec<u8>, usize)>> = spin::Mutex::new(Vec::new());
+static THREAD_LOCK: spin::Mutex<()> = spin::Mutex::new(());
+static CURRENT_THREAD: spin::Mutex<(i32, [u8; 32])> = spin::Mutex::new((
+    1,
+    *b"user_main\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0",
+));
+static INTERRUPT_LOCK: spin::Mutex<()> = spin::Mutex::new(());
+static IN_INTERRUPT: AtomicBool = AtomicBool::new(false);
+static SYSTEM_TIME: AtomicI64 = AtomicI64::new(0);
+static LOCAL_TIME: spin::Mutex<DateTime> = spin::Mutex::new(DateTime {
+    year: 2000,
+    month: 1,
+    day: 1,
+    hour: 0,
+    minute: 0,
+    second: 0,
+    microsecond: 0,
+});
+
+/// Run `f` and return every write it made.
+///
+/// Captures are serialised, so concurrently running tests will not see each other's output.
+pub fn capture<F: FnOnce()>(f: F) -> Vec<RecordedWrite> {
+    let _guard = CAPTURE_LOCK.lock();
+
+    WRITES.lock().clear();
+    f();
+    core::mem::take(&mut *WRITES.lock())
+}
+
+/// Run `f` as if it were running on the PSP thread `id`, named `name`.
+///
+/// Outside of this, the current thread is `user_main` with id 1. Calls are serialised, so
+/// concurrently running tests will not see each other's thread.
+pub fn with_thread<R, F: FnOnce() -> R>(id: i32, name: &str, f: F) -> R {
+    let _guard = THREAD_LOCK.lock();
+
+    let mut thread = (id, [0; 32]);
+    let len = name.len().min(31);
+    thread.1[..len].copy_from_slice(&name.as_bytes()[..len]);
+
+    let previous = core::mem::replace(&mut *CURRENT_THREAD.lock(), thread);
+    let result = f();
+    *CURRENT_THREAD.lock() = previous;
+
+    result
+}
+
+/// Run `f` as if it were an interrupt handler, with interrupts disabled.
+///
+/// Calls are serialised, so concurrently running tests will not see each other's interrupts.
+pub fn in_interrupt<R, F: FnOnce() -> R>(f: F) -> R {
+    let _guard = INTERRUPT_LOCK.lock();
+
+    IN_INTERRUPT.store(true, Ordering::SeqCst);
+    let result = f();
+    IN_INTERRUPT.store(false, Ordering::SeqCst);
+
+    result
+}
+
+pub(crate) unsafe fn sceKernelIsCpuIntrEnable() -> i32 {
+    !IN_INTERRUPT.load(Ordering::SeqCst) as i32
+}
+
+pub(crate) unsafe fn sceKernelGetThreadId() -> i32 {
+    CURRENT_THREAD.lock().0
+}
+
+/// Only the current thread can be queried.
+pub(crate) unsafe fn sceKernelReferThreadStatus(
+    uid: SceUid,
+    info: *mut SceKernelThreadInfo,
+) -> i32 {
+    let (id, name) = *CURRENT_THREAD.lock();
+
+    if uid.0 != id {
+        return -1;
+    }
+
+    (*info).name = name;
+    0
+}
+
+// Threads can't be created on the host, so anything relying on a PSP thread has to be driven
+// manually in tests.
+
+pub(crate) unsafe fn sceKernelCreateThread(
+    _name: *const u8,
+    _entry: SceKernelThreadEntry,
+    _init_priority: i32,
+    _stack_size: i32,
+    _attr: ThreadAttributes,
+    _option: *mut SceKernelThreadOptParam,
+) -> SceUid {
+    SceUid(-1)
+}
+
+pub(crate) unsafe fn sceKernelStartThread(
+    _id: SceUid,
+    _arg_len: usize,
+    _arg_p: *mut c_void,
+) -> i32 {
+    -1
+}
+
+pub(crate) unsafe fn sceKernelDeleteThread(_id: SceUid) -> i32 {
+    0
+}
+
+pub(crate) unsafe fn sceKernelSleepThread() -> i32 {
+    0
+}
+
+pub(crate) unsafe fn sceKernelWakeupThread(_id: SceUid) -> i32 {
+    0
+}
+
+pub(crate) unsafe fn sceKernelDelayThread(_delay: u32) -> i32 {
+    0
+}
+
+/// Set the value returned by the host version of `sceKernelGetSystemTimeWide`, in microseconds.
+pub fn set_system_time(micros: i64) {
+    SYSTEM_TIME.store(micros, Ordering::Relaxed);
+}
+
+pub(crate) unsafe fn sceKernelGetSystemTimeWide() -> i64 {
+    SYSTEM_TIME.load(Ordering::Relaxed)
+}
+
+/// Set the value returned by the host version of `sceRtcGetCurrentClockLocalTime`.
+pub fn set_local_time(time: DateTime) {
+    *LOCAL_TIME.lock() = time;
+}
+
+pub(crate) unsafe fn sceRtcGetCurrentClockLocalTime(time: *mut ScePspDateTime) -> i32 {
+    let local = *LOCAL_TIME.lock();
+
+    *time = ScePspDateTime {
+        year: local.year,
+        month: local.month,
+        day: local.day,
+        hour: local.hour,
+        minutes: local.minute,
+        seconds: local.second,
+        microseconds: local.microsecond,
+    };
+
+    0
+}
+
+/// Firmware version returned by the host version of `sceKernelDevkitVersion`, i.e. 6.60.
+pub const FIRMWARE_VERSION: u32 = 0x0606_0010;
+
+pub(crate) unsafe fn sceKernelDevkitVersion() -> u32 {
+    FIRMWARE_VERSION
+}
+
+/// Text segment address of the logger's module, as returned by the host version of
+/// `sceKernelQueryModuleInfo`.
+pub const MODULE_TEXT_ADDR: u32 = 0x0880_4000;
+
+/// Text segment size of the logger's module, as returned by the host version of
+/// `sceKernelQueryModuleInfo`.
+pub const MODULE_TEXT_SIZE: u32 = 0x0004_0000;
+
+/// The modules listed by the host version of `sceKernelGetModuleIdList`, with their text
+/// segments. The one without a text segment can't be queried, like a kernel module.
+const MODULES: [(SceUid, Option<(u32, u32)>); 3] = [
+    (SceUid(3), None),
+    (SceUid(2), Some((0x0890_0000, 0x0001_0000))),
+    (SceUid(1), Some((MODULE_TEXT_ADDR, MODULE_TEXT_SIZE))),
+];
+
+/// An address in the code of the logger's module.
+pub(crate) fn code_address() -> u32 {
+    MODULE_TEXT_ADDR + 0x100
+}
+
+pub(crate) unsafe fn sceKernelGetModuleIdList(
+    read_buf: *mut SceUid,
+    read_buf_size: i32,
+    id_count: *mut i32,
+) -> i32 {
+    let count = MODULES.len().min(read_buf_size.max(0) as usize);
+
+    for (index, (id, _)) in MODULES[..count].iter().enumerate() {
+        *read_buf.add(index) = *id;
+    }
+    *id_count = count as i32;
+    0
+}
+
+pub(crate) unsafe fn sceKernelQueryModuleInfo(uid: SceUid, info: *mut SceKernelModuleInfo) -> i32 {
+    if (*info).size != core::mem::size_of::<SceKernelModuleInfo>() {
+        return -1;
+    }
+
+    match MODULES.iter().find(|(id, _)| *id == uid) {
+        Some((_, Some((addr, size)))) => {
+            (*info).text_addr = *addr;
+            (*info).text_size = *size;
+            0
+        }
+        _ => -1,
+    }
+}
+
+pub(crate) unsafe fn sceKernelStdout() -> SceUid {
+    STDOUT_FD
+}
+
+pub(crate) unsafe fn sceKernelStderr() -> SceUid {
+    STDERR_FD
+}
+
+pub(crate) unsafe fn sceIoWrite(fd: SceUid, data: *const c_void, size: usize) -> i32 {
+    let data = core::slice::from_raw_parts(data as *const u8, size);
+
+    WRITES.lock().push(RecordedWrite {
+        fd,
+        data: data.to_vec(),
+    });
+
+    size as i32
+}
+
+/// Make a file readable through the host versions of `sceIoOpen` and `sceIoRead`.
+///
+/// Replaces any file previously added at the same path.
+pub fn add_file(path: &str, contents: &[u8]) {
+    let mut files = FILES.lock();
+
+    files.retain(|(existing, _)| existing != path.as_bytes());
+    files.push((path.as_bytes().to_vec(), contents.to_vec()));
+}
+
+/// Error returned by the host version of `sceIoOpen` when a file is opened for reading without
+/// having been added with [add_file]. This is the PSP's "file not found" error code.
+pub const FILE_NOT_FOUND: i32 = 0x8001_0002_u32 as i32;
+
+// The host backend does not touch the real file system. Files can be opened and written to,
+// with writes recorded against the returned descriptor, but nothing is persisted. Only files
+// added with `add_file` can be read.
+
+pub(crate) unsafe fn sceIoOpen(file: *const u8, flags: IoOpenFlags, _permissions: i32) -> SceUid {
+    let fd = SceUid(NEXT_FD.fetch_add(1, Ordering::Relaxed));
+
+    if flags.bits() & IoOpenFlags::RD_WR.bits() == IoOpenFlags::RD_ONLY.bits() {
+        let path = core::ffi::CStr::from_ptr(file as _).to_bytes();
+        let files = FILES.lock();
+
+        match files.iter().find(|(existing, _)| existing == path) {
+            Some((_, contents)) => OPEN_FILES.lock().push((fd, contents.clone(), 0)),
+            None => return SceUid(FILE_NOT_FOUND),
+        }
+    }
+
+    fd
+}
+
+pub(crate) unsafe fn sceIoRead(fd: SceUid, data: *mut c_void, size: u32) -> i32 {
+    let mut open_files = OPEN_FILES.lock();
+
+    let Some((_, contents, pos)) = open_files.iter_mut().find(|(open, _, _)| *open == fd) else {
+        return -1;
+    };
+
+    let len = (size as usize).min(contents.len() - *pos);
+    core::ptr::copy_nonoverlapping(contents[*pos..].as_ptr(), data as *mut u8, len);
+    *pos += len;
+
+    len as i32
+}
+
+pub(crate) unsafe fn sceIoClose(fd: SceUid) -> i32 {
+    OPEN_FILES.lock().retain(|(open, _, _)| *open != fd);
+    0
+}
+
+pub(crate) unsafe fn sceIoLseek(_fd: SceUid, _offset: i64, _whence: IoWhence) -> i64 {
+    0
+}
+
+pub(crate) unsafe fn sceIoMkdir(_dir: *const u8, _mode: i32) -> i32 {
+    0
+}
+
+pub(crate) unsafe fn sceIoRemove(_file: *const u8) -> i32 {
+    0
+}
+
+pub(crate) unsafe fn sceIoRename(_oldname: *const u8, _newname: *const u8) -> i32 {
+    0
+}
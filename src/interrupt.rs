@@ -0,0 +1,192 @@
+//! Logging from interrupt handlers.
+//!
+//! Sinks can't be written to in interrupt context, since writing files or stdio isn't allowed
+//! and the interrupted thread may hold their locks. Records logged from an interrupt handler are
+//! formatted into a preallocated lock-free queue instead, and written to their sinks by the next
+//! thread to log a record or flush the logger.
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use log::{Level, Record};
+
+use crate::format::{FormattedRecord, LineBuffer};
+use crate::lock::RECORD_LOCK;
+use crate::queue::LineQueue;
+use crate::sys::*;
+use crate::{write_to_sinks, OutputStream, PspLoggerConfig, MAX_LINE_LEN};
+
+/// Number of records that can wait to be written. Must be a power of two.
+const QUEUE_SLOTS: usize = 16;
+
+/// The levels, indexed by the byte each queued line starts with.
+const LEVELS: [Level; 5] = [
+    Level::Error,
+    Level::Warn,
+    Level::Info,
+    Level::Debug,
+    Level::Trace,
+];
+
+/// Records logged in interrupt context, waiting to be written.
+pub(crate) struct InterruptRecords<const SLOTS: usize> {
+    /// Formatted lines, each prefixed with the index of its level in [LEVELS].
+    queue: LineQueue<SLOTS, { MAX_LINE_LEN + 1 }>,
+
+    /// Records lost because the queue was full or the configuration was being changed.
+    dropped: AtomicUsize,
+}
+
+impl<const SLOTS: usize> InterruptRecords<SLOTS> {
+    pub(crate) const fn new() -> Self {
+        InterruptRecords {
+            queue: LineQueue::new(),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Format a record and queue it to be written later.
+    pub(crate) fn push(&self, config: &PspLoggerConfig, record: &Record) {
+        let level = record.level();
+
+        // Thread details are left out, since looking up names takes a lock, and the interrupted
+        // thread isn't the one logging.
+        let mut line = LineBuffer::<{ MAX_LINE_LEN + 1 }>::new();
+        let _ = write!(
+            line,
+            "{}{}",
+            level as usize - 1,
+            FormattedRecord::new(config.format.as_str(), record, config.time_source)
+                .without_thread()
+        );
+
+        if !self.queue.push(OutputStream::StdErr, line.finish()) {
+            self.count_dropped();
+        }
+    }
+
+    /// Count a record that couldn't be queued.
+    pub(crate) fn count_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of records that were lost.
+    pub(crate) fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Write every queued record to its sinks, oldest first.
+    pub(crate) fn drain(&self, config: &PspLoggerConfig) {
+        while self.queue.pop(|_, line| write_line(config, line)) {}
+    }
+}
+
+/// Records logged in interrupt context through the global logger.
+pub(crate) static INTERRUPT_RECORDS: InterruptRecords<QUEUE_SLOTS> = InterruptRecords::new();
+
+/// Whether the caller is running in interrupt context, or has suspended interrupts.
+///
+/// Interrupt handlers run with interrupts disabled, which is what this checks, so a thread that
+/// has suspended interrupts itself, e.g. with `sceKernelCpuSuspendIntr`, is treated the same way:
+/// its records are queued, with thread placeholders rendered as `?`. That is deliberate, since a
+/// thread can't block on a sink's syscalls or sleep on a lock with interrupts suspended either.
+/// `sceKernelIsIntrContext` would tell the two apart, but isn't bound by the `psp` crate.
+pub(crate) fn in_interrupt() -> bool {
+    unsafe { sceKernelIsCpuIntrEnable() == 0 }
+}
+
+/// Write a queued line, prefixed with its level's index, to the level's sinks.
+fn write_line(config: &PspLoggerConfig, line: &str) {
+    let Some(&level) = line
+        .as_bytes()
+        .first()
+        .and_then(|index| LEVELS.get(index.wrapping_sub(b'0') as usize))
+    else {
+        return;
+    };
+
+    let line = &line[1..];
+
+    write_to_sinks(
+        config,
+        level,
+        || Some(RECORD_LOCK.lock()),
+        |destinations, stream| {
+            for sink in destinations.iter() {
+                sink.write(stream, line);
+            }
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RingBuffer;
+    use std::boxed::Box;
+    use std::string::{String, ToString};
+    use std::vec::Vec;
+
+    fn lines(ring: &RingBuffer<4>) -> Vec<String> {
+        let mut lines = Vec::new();
+        ring.for_each(|line| lines.push(line.to_string()));
+        lines
+    }
+
+    #[test]
+    fn queued_records_are_written_to_their_sinks_when_drained() {
+        let records = InterruptRecords::<4>::new();
+        let errors: &'static RingBuffer<4> = Box::leak(Box::new(RingBuffer::new()));
+        let others: &'static RingBuffer<4> = Box::leak(Box::new(RingBuffer::new()));
+        let config = PspLoggerConfig::new(log::LevelFilter::Trace)
+            .with_format("{level} {msg}")
+            .with_sink(others)
+            .with_error_destinations(crate::Destinations::new().with(errors));
+
+        for (level, msg) in [(Level::Error, "one"), (Level::Trace, "two")] {
+            records.push(
+                &config,
+                &Record::builder()
+                    .level(level)
+                    .args(format_args!("{}", msg))
+                    .build(),
+            );
+        }
+        assert!(lines(others).is_empty());
+
+        records.drain(&config);
+
+        assert_eq!(lines(errors), ["ERROR one"]);
+        assert_eq!(lines(others), ["TRACE two"]);
+    }
+
+    #[test]
+    fn thread_placeholders_are_unknown() {
+        let records = InterruptRecords::<2>::new();
+        let ring: &'static RingBuffer<4> = Box::leak(Box::new(RingBuffer::new()));
+        let config = PspLoggerConfig::new(log::LevelFilter::Trace)
+            .with_format("{thread_id} {thread} {msg}")
+            .with_sink(ring);
+        let record = Record::builder().args(format_args!("vblank")).build();
+
+        crate::host::with_thread(0x0123abcd, "render", || {
+            crate::host::in_interrupt(|| records.push(&config, &record));
+        });
+        records.drain(&config);
+
+        assert_eq!(lines(ring), ["? ? vblank"]);
+    }
+
+    #[test]
+    fn records_are_dropped_when_full() {
+        let records = InterruptRecords::<2>::new();
+        let config = PspLoggerConfig::new(log::LevelFilter::Trace);
+        let record = Record::builder().args(format_args!("tick")).build();
+
+        for _ in 0..3 {
+            records.push(&config, &record);
+        }
+
+        assert_eq!(records.dropped(), 1);
+    }
+}
@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 //! # psp-logger
 //! A logger capable of outputting to the PSP's stdout and stderr.
@@ -25,16 +25,64 @@
 //! error!("This will also be logged to stder.");
 //!
 //! ```
+//!
+//! # Host builds
+//! When built for anything other than the PSP, the syscalls used by the logger are replaced by
+//! the [host] backend, which records output in memory instead. This allows the logger and any
+//! custom [LogSink] to be tested on a development machine.
+#[cfg(any(feature = "alloc", not(target_os = "psp")))]
 extern crate alloc;
 
-use core::fmt::Arguments;
+mod backtrace;
+pub mod binary;
+mod color;
+mod config;
+mod crash;
+pub mod dump;
+mod filter;
+mod format;
+#[cfg(not(target_os = "psp"))]
+pub mod host;
+mod interrupt;
+mod lock;
+mod module;
+mod panic;
+mod queue;
+mod reentrancy;
+mod sink;
+mod sys;
+mod thread;
+mod time;
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
-use alloc::format;
+use color::RESET;
+use format::{FormattedRecord, LineBuffer, Template, DEFAULT_FORMAT};
+use interrupt::INTERRUPT_RECORDS;
+use lock::{SleepLock, SleepLockGuard, SleepRwLock, RECORD_LOCK};
 use log::{Level, LevelFilter, Metadata, Record};
-use psp::sys::*;
+use thread::ThreadPrefix;
+
+pub use backtrace::{Backtrace, MAX_FRAMES};
+pub use color::{Color, LevelColors};
+pub use config::{ConfigError, ConfigErrorKind};
+#[cfg(all(target_os = "psp", feature = "exception-handler"))]
+pub use crash::install_exception_handler;
+pub use crash::{report_crash, CrashReport, RegisterSet};
+pub use filter::{DirectiveError, Directives, MAX_DIRECTIVES, MAX_TARGET_LEN};
+pub use format::{MAX_LINE_LEN, MAX_TEMPLATE_LEN};
+pub use module::TextSegment;
+pub use panic::{log_panic, log_panic_message};
+pub use sink::{
+    AsyncSink, BufferedSink, Destinations, FileSink, FileSystem, LogSink, OverflowPolicy,
+    PspFileSystem, RingBuffer, StdioSink, Tee, MAX_DESTINATIONS,
+};
+pub use thread::ThreadInfo;
+pub use time::{DateTime, RtcClock, SystemClock, TimeSource, Timestamp};
 
 /// Enum holding the possible output streams that the logs can be written to.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum OutputStream {
     StdOut,
     StdErr,
@@ -44,6 +92,8 @@ pub enum OutputStream {
 ///
 /// # Examples
 /// ```
+/// use log::debug;
+///
 /// // Create logger for Debug and up.
 /// // All logs will be written to stderr.
 /// let config = psp_logger::PspLoggerConfig::new(log::LevelFilter::Debug);
@@ -53,6 +103,8 @@ pub enum OutputStream {
 /// ```
 ///
 /// ```
+/// use log::info;
+///
 /// // Create logger for Info and up.
 /// // Info logs will go to stdout, the rest will go to stderr.
 /// let config = psp_logger::PspLoggerConfig::new(log::LevelFilter::Info)
@@ -62,62 +114,458 @@ pub enum OutputStream {
 ///
 /// info!("I'm an info log!");
 /// ```
+#[derive(Clone)]
 pub struct PspLoggerConfig {
     error_stream: OutputStream,
     warn_stream: OutputStream,
     info_stream: OutputStream,
     debug_stream: OutputStream,
     trace_stream: OutputStream,
+    error_destinations: Destinations,
+    warn_destinations: Destinations,
+    info_destinations: Destinations,
+    debug_destinations: Destinations,
+    trace_destinations: Destinations,
     level_filter: LevelFilter,
+    directives: Directives,
+    format: Template,
+    time_source: &'static dyn TimeSource,
+    colors: Option<LevelColors>,
+    thread_info: ThreadInfo,
+    flush_level: LevelFilter,
+    backtrace_level: LevelFilter,
 }
 
 /// The actual logger instance.
 pub struct PspLogger {}
 
 static LOGGER: PspLogger = PspLogger {};
-static LOGGER_CONF: spin::Once<PspLoggerConfig> = spin::Once::new();
+static LOGGER_CONF: SleepRwLock<Option<PspLoggerConfig>> = SleepRwLock::new(None);
+
+/// Held while the logger is initialised or reconfigured, so that only one change is made at a
+/// time. [LOGGER_CONF] itself is only written to once the new configuration is ready.
+static UPDATE_LOCK: SleepLock = SleepLock::new();
+
+/// Whether [LOGGER] has been installed with the `log` crate. It stays installed if opening the
+/// sinks then fails, so a later [PspLogger::init] only needs to open them.
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Log where the module's code was loaded, so that addresses in the log can be symbolized.
+///
+/// This is an info record with the target `psp_logger`, so is subject to the level filter and
+/// directives like any other.
+fn log_text_segment(config: &PspLoggerConfig) {
+    if let Some(text) = module::current_text_segment() {
+        let args = format_args!("{}", text);
+        let record = Record::builder()
+            .level(Level::Info)
+            .target("psp_logger")
+            .args(args)
+            .build();
+
+        if config.enabled(record.metadata()) {
+            write_record(config, &record);
+        }
+    }
+}
 
-unsafe fn psp_write(stream: OutputStream, args: &Arguments) {
-    let fh = match stream {
-        OutputStream::StdErr => sceKernelStderr(),
-        OutputStream::StdOut => sceKernelStdout(),
+fn write_record(config: &PspLoggerConfig, record: &Record) {
+    write_record_with_lock(config, record, || Some(RECORD_LOCK.lock()));
+}
+
+/// Write a record to its sinks, holding the guard returned by `lock` while doing so.
+///
+/// The record is formatted and its backtrace captured before taking the lock, so that other
+/// threads are only held up while the lines are written. If `lock` returns `None`, the record
+/// is written anyway.
+fn write_record_with_lock(
+    config: &PspLoggerConfig,
+    record: &Record,
+    lock: impl FnOnce() -> Option<SleepLockGuard<'static>>,
+) {
+    let level = record.metadata().level();
+    let destinations = config.get_destinations(level);
+
+    // Formatting stops early once the buffer is full, so the error is expected.
+    let mut line = LineBuffer::<MAX_LINE_LEN>::new();
+    let _ = write!(
+        line,
+        "{}{}",
+        ThreadPrefix(config.thread_info),
+        FormattedRecord::new(config.format.as_str(), record, config.time_source)
+    );
+    let line = line.finish();
+
+    let color = config
+        .colors
+        .map_or(Color::None, |colors| colors.get(level));
+
+    // Leave room for the escape codes so they are never truncated.
+    let mut colored_line = LineBuffer::<{ MAX_LINE_LEN + 16 }>::new();
+    let colored_line = if color != Color::None && destinations.iter().any(|s| s.supports_color()) {
+        let _ = write!(
+            colored_line,
+            "{}{}{}",
+            color.escape(),
+            line.trim_end_matches('\n'),
+            RESET
+        );
+        Some(colored_line.finish())
+    } else {
+        None
     };
 
-    let msg = alloc::fmt::format(*args);
-    let msg = format!("{}\n\0", msg);
+    let backtrace = (level <= config.backtrace_level)
+        .then(Backtrace::capture)
+        .filter(|backtrace| !backtrace.is_empty());
+    let mut backtrace_line = LineBuffer::<MAX_LINE_LEN>::new();
+    let backtrace_line = match backtrace {
+        Some(backtrace) => {
+            let _ = write!(backtrace_line, "{}", backtrace);
+            Some(backtrace_line.finish())
+        }
+        None => None,
+    };
+
+    write_to_sinks(config, level, lock, |destinations, stream| {
+        for sink in destinations.iter() {
+            match colored_line {
+                Some(colored_line) if sink.supports_color() => sink.write(stream, colored_line),
+                _ => sink.write(stream, line),
+            }
+        }
+
+        if let Some(backtrace_line) = backtrace_line {
+            for sink in destinations.iter() {
+                sink.write(stream, backtrace_line);
+            }
+        }
+    });
+}
+
+/// Call `write` with the sinks and stream for `level`, holding the guard returned by `lock`, then
+/// flush the sinks if `level` is at or above the flush level.
+///
+/// Every record, whether text, queued from an interrupt handler or binary, reaches the sinks
+/// through here.
+pub(crate) fn write_to_sinks(
+    config: &PspLoggerConfig,
+    level: Level,
+    lock: impl FnOnce() -> Option<SleepLockGuard<'static>>,
+    write: impl FnOnce(&Destinations, OutputStream),
+) {
+    let stream = config.get_stream(level);
+    let destinations = config.get_destinations(level);
+
+    let _guard = lock();
+
+    write(destinations, stream);
+
+    if level <= config.flush_level {
+        for sink in destinations.iter() {
+            sink.flush();
+        }
+    }
+}
+
+/// Queue a record logged in interrupt context, to be written by the next thread that logs.
+fn log_from_interrupt(record: &Record) {
+    // The interrupted thread may be changing the configuration, and can't finish until the
+    // interrupt handler returns.
+    let Some(config) = LOGGER_CONF.try_read() else {
+        INTERRUPT_RECORDS.count_dropped();
+        return;
+    };
+
+    if let Some(config) = config.as_ref() {
+        if config.enabled(record.metadata()) {
+            INTERRUPT_RECORDS.push(config, record);
+        }
+    }
+}
+
+/// Number of records dropped because they were logged while logging a nested record, or by a
+/// thread that couldn't be tracked.
+static NESTED_RECORDS_DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Write a record logged while the same thread was already logging another.
+///
+/// The record goes straight to [StdioSink], without taking the record lock or touching the
+/// other sinks, since the outer record may be part way through using them.
+fn write_nested_record(config: &PspLoggerConfig, record: &Record) {
+    let mut line = LineBuffer::<MAX_LINE_LEN>::new();
+    let _ = write!(
+        line,
+        "{}",
+        FormattedRecord::new(config.format.as_str(), record, config.time_source)
+    );
+
+    StdioSink.write(config.get_stream(record.level()), line.finish());
+}
+
+/// Apply the settings file at `path` to `config`, falling back to `config` itself if that fails.
+///
+/// A missing file isn't treated as an error.
+fn load_settings(
+    config: PspLoggerConfig,
+    path: &str,
+    sinks: &[(&str, &'static dyn LogSink)],
+) -> (PspLoggerConfig, Option<ConfigError>) {
+    match config.apply_file(path, sinks) {
+        Ok(loaded) => (loaded, None),
+        Err(ConfigError::Io(sys::SCE_ERROR_ERRNO_ENOENT)) => (config, None),
+        Err(error) => (config, Some(error)),
+    }
+}
+
+/// Report a settings file that couldn't be used.
+///
+/// This goes straight to stderr rather than through the logger, so that it isn't filtered out by
+/// the configuration that was used instead, or lost if the logger fails to initialise.
+fn warn_settings_ignored(path: &str, error: ConfigError) {
+    let mut line = LineBuffer::<MAX_LINE_LEN>::new();
+    let _ = write!(line, "psp_logger: ignoring {}: {}", path, error);
+
+    StdioSink.write(OutputStream::StdErr, line.finish());
+}
+
+/// Error returned when the logger can't be initialised or reconfigured.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PspLoggerError {
+    /// [PspLogger::init] has already been called successfully.
+    AlreadyInitialised,
+
+    /// The logger hasn't been initialised, so can't be reconfigured.
+    NotInitialised,
 
-    sceIoWrite(fh, msg.as_ptr() as _, msg.len());
+    /// A different logger has already been installed with the `log` crate.
+    AnotherLoggerInstalled,
+
+    /// A sink failed to open, with the PSP error code returned by [LogSink::open].
+    SinkOpen(i32),
+}
+
+impl core::fmt::Display for PspLoggerError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PspLoggerError::AlreadyInitialised => write!(f, "logger is already initialised"),
+            PspLoggerError::NotInitialised => write!(f, "logger is not initialised"),
+            PspLoggerError::AnotherLoggerInstalled => {
+                write!(f, "another logger is already installed")
+            }
+            PspLoggerError::SinkOpen(code) => write!(f, "could not open sink: {:#010x}", code),
+        }
+    }
 }
 
 impl log::Log for PspLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= LOGGER_CONF.get().unwrap().level_filter
+        // An interrupted thread may hold the lock, so don't wait for it in interrupt context.
+        let config = if interrupt::in_interrupt() {
+            LOGGER_CONF.try_read()
+        } else {
+            Some(LOGGER_CONF.read())
+        };
+
+        config.is_some_and(|config| {
+            config
+                .as_ref()
+                .is_some_and(|config| config.enabled(metadata))
+        })
     }
 
     fn log(&self, record: &Record) {
-        let output = LOGGER_CONF
-            .get()
-            .unwrap()
-            .get_stream(record.metadata().level());
+        if interrupt::in_interrupt() {
+            log_from_interrupt(record);
+            return;
+        }
+
+        let entry = reentrancy::enter();
+        if entry.depth() > 1 {
+            NESTED_RECORDS_DROPPED.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
 
-        if self.enabled(record.metadata()) {
-            unsafe { psp_write(output, record.args()) }
+        // Hold the lock while writing, so the record is written with a single configuration.
+        let config = LOGGER_CONF.read();
+
+        if let Some(config) = config.as_ref() {
+            if !config.enabled(record.metadata()) {
+                return;
+            }
+
+            match entry.depth() {
+                0 => {
+                    INTERRUPT_RECORDS.drain(config);
+                    write_record(config, record);
+                }
+                _ => write_nested_record(config, record),
+            }
         }
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        if let Some(config) = LOGGER_CONF.read().as_ref() {
+            INTERRUPT_RECORDS.drain(config);
+            config.flush();
+        }
+    }
 }
 
 impl PspLogger {
     /// Initialise the logger.
     ///
+    /// Every configured sink is opened first, so that problems such as an unwritable log file
+    /// are reported here rather than silently losing records later. Once initialised, the
+    /// address the module's code was loaded at is logged at info level with the target
+    /// `psp_logger`, if that is enabled. See [TextSegment].
+    ///
     /// # Arguments
     /// - `config`: Logging configuration to be used.
-    pub fn init(config: PspLoggerConfig) -> Result<(), log::SetLoggerError> {
-        let level_filter = config.level_filter;
+    ///
+    /// # Errors
+    /// - [PspLoggerError::AlreadyInitialised] if the logger has already been initialised. Use
+    ///   [reconfigure](Self::reconfigure) to change its configuration instead.
+    /// - [PspLoggerError::AnotherLoggerInstalled] if a different logger is already in use. No
+    ///   sinks are opened.
+    /// - [PspLoggerError::SinkOpen] if a sink fails to open. Records are ignored until `init`
+    ///   succeeds.
+    pub fn init(config: PspLoggerConfig) -> Result<(), PspLoggerError> {
+        let _update = UPDATE_LOCK.lock();
 
-        LOGGER_CONF.call_once(|| config);
-        log::set_logger(&LOGGER).map(|()| log::set_max_level(level_filter))
+        if LOGGER_CONF.read().is_some() {
+            return Err(PspLoggerError::AlreadyInitialised);
+        }
+
+        // Installed before any sinks are opened, so none are left open if another logger is in
+        // use. Without a configuration, the logger ignores records until then.
+        if !INSTALLED.load(Ordering::Relaxed) {
+            log::set_logger(&LOGGER).map_err(|_| PspLoggerError::AnotherLoggerInstalled)?;
+            INSTALLED.store(true, Ordering::Relaxed);
+        }
+
+        // Sinks are opened without holding the configuration, in case opening one logs.
+        config.open_sinks().map_err(PspLoggerError::SinkOpen)?;
+
+        let level_filter = config.max_level();
+        *LOGGER_CONF.write() = Some(config);
+        log::set_max_level(level_filter);
+
+        let _entry = reentrancy::enter();
+        if let Some(config) = LOGGER_CONF.read().as_ref() {
+            log_text_segment(config);
+        }
+
+        Ok(())
+    }
+
+    /// Initialise the logger, applying the settings in a file on top of `config`.
+    ///
+    /// This allows a build to be switched to more verbose logging without rebuilding it. See
+    /// [PspLoggerConfig::apply_file] for the file's contents.
+    ///
+    /// If the file doesn't exist, `config` is used as it is. If it can't be read or is invalid,
+    /// `config` is used and a warning describing the problem is written to stderr, whatever the
+    /// level filter.
+    ///
+    /// # Arguments
+    /// - `config`: Logging configuration to use when the file doesn't change it.
+    /// - `path`: Path to the settings file, e.g. `ms0:/PSP/GAME/MYAPP/logging.cfg`.
+    /// - `sinks`: Sinks that the file can refer to, by name.
+    ///
+    /// # Errors
+    /// The same as [init](Self::init).
+    pub fn init_from_file(
+        config: PspLoggerConfig,
+        path: &str,
+        sinks: &[(&str, &'static dyn LogSink)],
+    ) -> Result<(), PspLoggerError> {
+        let (config, error) = load_settings(config, path, sinks);
+        if let Some(error) = error {
+            warn_settings_ignored(path, error);
+        }
+
+        Self::init(config)
+    }
+
+    /// Change the configuration of the running logger.
+    ///
+    /// `f` is given a copy of the current configuration and returns the one to use from now on,
+    /// so any of the [PspLoggerConfig] builder methods can be used. Threads that are logging at
+    /// the same time finish their record with the old configuration first.
+    ///
+    /// Only one reconfiguration runs at a time. `f` runs and the new sinks are opened without
+    /// holding up threads that are logging, so both may log themselves. Those records use the
+    /// old configuration.
+    ///
+    /// # Errors
+    /// - [PspLoggerError::NotInitialised] if the logger hasn't been initialised.
+    /// - [PspLoggerError::SinkOpen] if a sink in the new configuration fails to open. The old
+    ///   configuration stays in use.
+    ///
+    /// # Examples
+    /// ```
+    /// use psp_logger::{OutputStream, PspLogger, PspLoggerConfig};
+    ///
+    /// PspLogger::init(PspLoggerConfig::new(log::LevelFilter::Info)).unwrap();
+    ///
+    /// // A bug has reproduced, so get as much detail as possible.
+    /// PspLogger::reconfigure(|config| {
+    ///     config
+    ///         .with_level_filter(log::LevelFilter::Trace)
+    ///         .with_trace_stream(OutputStream::StdOut)
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn reconfigure<F: FnOnce(PspLoggerConfig) -> PspLoggerConfig>(
+        f: F,
+    ) -> Result<(), PspLoggerError> {
+        let _update = UPDATE_LOCK.lock();
+
+        let current = LOGGER_CONF.read().clone();
+        let new = f(current.ok_or(PspLoggerError::NotInitialised)?);
+        new.open_sinks().map_err(PspLoggerError::SinkOpen)?;
+
+        let level_filter = new.max_level();
+        *LOGGER_CONF.write() = Some(new);
+        log::set_max_level(level_filter);
+
+        Ok(())
+    }
+
+    /// Change the level filter of the running logger.
+    ///
+    /// See [reconfigure](Self::reconfigure) for changing anything else.
+    pub fn set_level_filter(level_filter: LevelFilter) -> Result<(), PspLoggerError> {
+        Self::reconfigure(|config| config.with_level_filter(level_filter))
+    }
+
+    /// Replace the per-target directives of the running logger.
+    ///
+    /// See [reconfigure](Self::reconfigure) for changing anything else.
+    pub fn set_directives(directives: Directives) -> Result<(), PspLoggerError> {
+        Self::reconfigure(|config| config.with_directives(directives))
+    }
+
+    /// Number of records dropped because they were logged too deep inside another record.
+    ///
+    /// A record logged while its thread is already logging one, e.g. by a sink or by formatting
+    /// an argument, is written straight to [StdioSink] rather than through the usual sinks.
+    /// Records logged while writing that one are dropped instead, and counted here, as are
+    /// records logged while too many other threads are logging for nesting to be tracked.
+    pub fn nested_records_dropped() -> usize {
+        NESTED_RECORDS_DROPPED.load(Ordering::Relaxed)
+    }
+
+    /// Number of records logged in interrupt context that were lost.
+    ///
+    /// Records can't be written to sinks from an interrupt handler, or anywhere else with
+    /// interrupts disabled. They are queued instead, and written by the next thread to log a
+    /// record or flush the logger. Records are lost if the queue fills up before then, or if the
+    /// interrupt arrived while the logger was being reconfigured. Binary records logged with
+    /// [log_binary] in interrupt context are always lost.
+    pub fn interrupt_records_dropped() -> usize {
+        INTERRUPT_RECORDS.dropped()
     }
 }
 
@@ -136,10 +584,263 @@ impl PspLoggerConfig {
             info_stream: OutputStream::StdErr,
             debug_stream: OutputStream::StdErr,
             trace_stream: OutputStream::StdErr,
+            error_destinations: Destinations::new().with(&StdioSink),
+            warn_destinations: Destinations::new().with(&StdioSink),
+            info_destinations: Destinations::new().with(&StdioSink),
+            debug_destinations: Destinations::new().with(&StdioSink),
+            trace_destinations: Destinations::new().with(&StdioSink),
             level_filter,
+            directives: Directives::new(),
+            format: Template::Static(DEFAULT_FORMAT),
+            time_source: &SystemClock,
+            colors: None,
+            thread_info: ThreadInfo::None,
+            flush_level: LevelFilter::Error,
+            backtrace_level: LevelFilter::Error,
         }
     }
 
+    /// Set the filter controlling which log levels are actually logged.
+    ///
+    /// This replaces the `level_filter` given to [new](Self::new), and is mostly useful with
+    /// [PspLogger::reconfigure]. It also replaces the level of a directive without a target set
+    /// with [with_directives](Self::with_directives), so whichever of the two is set last
+    /// applies. Directives for specific targets are kept.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_level_filter(mut self, level_filter: LevelFilter) -> Self {
+        self.level_filter = level_filter;
+        self.directives.clear_default_level();
+        self
+    }
+
+    /// Apply settings from a text file on top of this configuration.
+    ///
+    /// Each line of the file holds a `key = value` setting, and lines starting with `#` are
+    /// comments. Settings that aren't in the file keep their current values.
+    ///
+    /// | Key | Value |
+    /// | --- | --- |
+    /// | `level` | Level filter, e.g. `debug` |
+    /// | `flush_level` | See [with_flush_level](Self::with_flush_level) |
+    /// | `backtrace_level` | See [with_backtrace_level](Self::with_backtrace_level) |
+    /// | `format` | See [with_format](Self::with_format) |
+    /// | `directives` | See [Directives] |
+    /// | `stream`, `error_stream`, ... `trace_stream` | `stdout` or `stderr` |
+    /// | `sinks`, `error_sinks`, ... `trace_sinks` | Comma separated sink names |
+    ///
+    /// Keys without a level apply to every level. Sinks are looked up by name in `sinks`, and
+    /// `stdio` always refers to [StdioSink].
+    ///
+    /// Returns the new configuration, or the first problem found along with its line number.
+    ///
+    /// # Examples
+    /// ```
+    /// use psp_logger::{FileSink, LogSink, PspLoggerConfig};
+    ///
+    /// static LOG_FILE: FileSink = FileSink::new("ms0:/PSP/GAME/MYAPP/app.log", 64 * 1024, 3);
+    ///
+    /// let settings = "\
+    ///     level = trace
+    ///     sinks = stdio, file
+    ///     debug_stream = stdout
+    /// ";
+    ///
+    /// let sinks: [(&str, &'static dyn LogSink); 1] = [("file", &LOG_FILE)];
+    /// let config = PspLoggerConfig::new(log::LevelFilter::Info)
+    ///     .apply_settings(settings, &sinks)
+    ///     .unwrap();
+    /// ```
+    pub fn apply_settings(
+        &self,
+        settings: &str,
+        sinks: &[(&str, &'static dyn LogSink)],
+    ) -> Result<PspLoggerConfig, ConfigError> {
+        config::apply(self.clone(), settings, sinks)
+    }
+
+    /// Read a settings file and apply it on top of this configuration.
+    ///
+    /// See [apply_settings](Self::apply_settings) for the file's contents. The file must be
+    /// smaller than 2KiB.
+    pub fn apply_file(
+        &self,
+        path: &str,
+        sinks: &[(&str, &'static dyn LogSink)],
+    ) -> Result<PspLoggerConfig, ConfigError> {
+        config::apply_file(self.clone(), path, sinks)
+    }
+
+    /// Filter records by target, using [Directives].
+    ///
+    /// A record whose target matches a directive is logged according to that directive's level,
+    /// instead of `level_filter`. A directive without a target replaces `level_filter`, until
+    /// [with_level_filter](Self::with_level_filter) is called.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    ///
+    /// # Examples
+    /// ```
+    /// use psp_logger::{Directives, PspLoggerConfig};
+    ///
+    /// let config = PspLoggerConfig::new(log::LevelFilter::Info)
+    ///     .with_directives(Directives::parse("my_game::render=trace,my_game::audio=off").unwrap());
+    /// ```
+    pub fn with_directives(mut self, directives: Directives) -> Self {
+        self.directives = directives;
+        self
+    }
+
+    /// Set the template used to format each line.
+    ///
+    /// Placeholders in braces are replaced with details of the record:
+    /// - `{time}`: The current time, from the configured [TimeSource].
+    /// - `{level}`: The log level, e.g. `WARN`.
+    /// - `{target}`: The record's target, which defaults to the module path.
+    /// - `{module}`: The module path of the log call.
+    /// - `{file}` and `{line}`: The source location of the log call.
+    /// - `{thread_id}` and `{thread}`: The id and name of the thread that logged the record.
+    /// - `{msg}`: The log message itself.
+    ///
+    /// A minimum width can be given after a colon, optionally preceded by `<`, `>` or `^` to
+    /// left, right or centre align the value, e.g. `{level:5}` or `{line:>4}`.
+    /// Use `{{` and `}}` for literal braces. Unknown placeholders are written out unchanged.
+    ///
+    /// The default template is `{msg}`.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    ///
+    /// # Examples
+    /// ```
+    /// let config = psp_logger::PspLoggerConfig::new(log::LevelFilter::Info)
+    ///     .with_format("{time} {level:5} [{target}] {file}:{line} {msg}");
+    /// ```
+    pub fn with_format(mut self, format: &'static str) -> Self {
+        self.format = Template::Static(format);
+        self
+    }
+
+    /// Set where the `{time}` placeholder gets the time from.
+    ///
+    /// Defaults to [SystemClock], giving microseconds since boot. Use [RtcClock] for the local
+    /// date and time instead.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_time_source(mut self, time_source: &'static dyn TimeSource) -> Self {
+        self.time_source = time_source;
+        self
+    }
+
+    /// Prefix each line with details of the thread that logged it.
+    ///
+    /// Thread names are looked up with `sceKernelReferThreadStatus` the first time a thread logs
+    /// and cached after that. The same details are available to [with_format](Self::with_format)
+    /// templates as `{thread_id}` and `{thread}`.
+    ///
+    /// Defaults to [ThreadInfo::None].
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_thread_info(mut self, thread_info: ThreadInfo) -> Self {
+        self.thread_info = thread_info;
+        self
+    }
+
+    /// Flush a record's sinks straight after writing it if it is at or above this level.
+    ///
+    /// This ensures important records reach sinks that buffer their output, such as
+    /// [BufferedSink] or [AsyncSink], before anything else happens.
+    ///
+    /// Defaults to [LevelFilter::Error]. Use [LevelFilter::Off] to never flush automatically.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_flush_level(mut self, flush_level: LevelFilter) -> Self {
+        self.flush_level = flush_level;
+        self
+    }
+
+    /// Follow a record with a [Backtrace] line if it is at or above this level.
+    ///
+    /// The backtrace is found by scanning the stack, so it is best-effort and takes some time.
+    /// This includes panics logged with [log_panic].
+    ///
+    /// Defaults to [LevelFilter::Error]. Use [LevelFilter::Off] to never capture backtraces.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_backtrace_level(mut self, backtrace_level: LevelFilter) -> Self {
+        self.backtrace_level = backtrace_level;
+        self
+    }
+
+    /// Colour each line according to its level, using ANSI escape codes.
+    ///
+    /// This is intended for viewing output in PSPLink's terminal. Colours are only written to
+    /// sinks that report [LogSink::supports_color], such as [StdioSink], so files and the ring
+    /// buffer stay free of escape codes.
+    ///
+    /// Colours are disabled by default.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_colors(mut self, colors: LevelColors) -> Self {
+        self.colors = Some(colors);
+        self
+    }
+
+    /// Send all log output to a [LogSink] instead of the default [StdioSink].
+    ///
+    /// The sink still receives the [OutputStream] each level is mapped to.
+    /// This replaces any destinations previously set with the `with_*_destinations` methods.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_sink(self, sink: &'static dyn LogSink) -> Self {
+        let destinations = Destinations::new().with(sink);
+
+        self.with_error_destinations(destinations)
+            .with_warn_destinations(destinations)
+            .with_info_destinations(destinations)
+            .with_debug_destinations(destinations)
+            .with_trace_destinations(destinations)
+    }
+
+    /// Write error logs to a set of [LogSink]s.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_error_destinations(mut self, destinations: Destinations) -> Self {
+        self.error_destinations = destinations;
+        self
+    }
+
+    /// Write warn logs to a set of [LogSink]s.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_warn_destinations(mut self, destinations: Destinations) -> Self {
+        self.warn_destinations = destinations;
+        self
+    }
+
+    /// Write info logs to a set of [LogSink]s.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_info_destinations(mut self, destinations: Destinations) -> Self {
+        self.info_destinations = destinations;
+        self
+    }
+
+    /// Write debug logs to a set of [LogSink]s.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_debug_destinations(mut self, destinations: Destinations) -> Self {
+        self.debug_destinations = destinations;
+        self
+    }
+
+    /// Write trace logs to a set of [LogSink]s.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub fn with_trace_destinations(mut self, destinations: Destinations) -> Self {
+        self.trace_destinations = destinations;
+        self
+    }
+
     /// Map the error log level to an [OutputStream]
     ///
     /// Returns the struct to allow the method to be chained.
@@ -180,6 +881,25 @@ impl PspLoggerConfig {
         self
     }
 
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let level_filter = self
+            .directives
+            .level_for(metadata.target())
+            .or(self.directives.default_level())
+            .unwrap_or(self.level_filter);
+
+        metadata.level() <= level_filter
+    }
+
+    /// The most verbose level that any record could be logged at.
+    fn max_level(&self) -> LevelFilter {
+        let default = self.directives.default_level().unwrap_or(self.level_filter);
+
+        self.directives
+            .max_level()
+            .map_or(default, |max| max.max(default))
+    }
+
     fn get_stream(&self, level: Level) -> OutputStream {
         match level {
             Level::Error => self.error_stream,
@@ -189,4 +909,747 @@ impl PspLoggerConfig {
             Level::Trace => self.trace_stream,
         }
     }
+
+    fn get_destinations(&self, level: Level) -> &Destinations {
+        match level {
+            Level::Error => &self.error_destinations,
+            Level::Warn => &self.warn_destinations,
+            Level::Info => &self.info_destinations,
+            Level::Debug => &self.debug_destinations,
+            Level::Trace => &self.trace_destinations,
+        }
+    }
+
+    /// Flush every sink used by any level, once each.
+    fn flush(&self) {
+        self.for_each_sink(|sink| sink.flush());
+    }
+
+    /// Open every sink used by any level, once each, stopping at the first failure.
+    fn open_sinks(&self) -> Result<(), i32> {
+        let mut result = Ok(());
+
+        self.for_each_sink(|sink| {
+            if result.is_ok() {
+                result = sink.open();
+            }
+        });
+
+        result
+    }
+
+    /// Call `f` with every sink used by any level, once each.
+    fn for_each_sink<F: FnMut(&dyn LogSink)>(&self, mut f: F) {
+        let all = [
+            &self.error_destinations,
+            &self.warn_destinations,
+            &self.info_destinations,
+            &self.debug_destinations,
+            &self.trace_destinations,
+        ];
+
+        for (i, destinations) in all.iter().enumerate() {
+            for sink in destinations.iter() {
+                let seen = all[..i]
+                    .iter()
+                    .any(|earlier| earlier.iter().any(|s| core::ptr::addr_eq(s, sink)));
+
+                if !seen {
+                    f(sink);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::{self, RecordedWrite, STDERR_FD, STDOUT_FD};
+    use std::boxed::Box;
+    use std::string::{String, ToString};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    /// Sink recording everything written to it.
+    #[derive(Default)]
+    struct MemorySink {
+        lines: Mutex<Vec<(OutputStream, String)>>,
+        flushes: AtomicUsize,
+    }
+
+    impl MemorySink {
+        fn leak() -> &'static MemorySink {
+            Box::leak(Box::default())
+        }
+
+        fn lines(&self) -> Vec<(OutputStream, String)> {
+            self.lines.lock().unwrap().clone()
+        }
+    }
+
+    impl LogSink for MemorySink {
+        fn write(&self, stream: OutputStream, line: &str) {
+            self.lines.lock().unwrap().push((stream, line.to_string()));
+        }
+
+        fn flush(&self) {
+            self.flushes.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Sink that only counts how often it is opened.
+    struct OpenSink {
+        result: Result<(), i32>,
+        opens: AtomicUsize,
+    }
+
+    impl OpenSink {
+        fn leak(result: Result<(), i32>) -> &'static Self {
+            Box::leak(Box::new(OpenSink {
+                result,
+                opens: AtomicUsize::new(0),
+            }))
+        }
+    }
+
+    impl LogSink for OpenSink {
+        fn write(&self, _stream: OutputStream, _line: &str) {}
+
+        fn open(&self) -> Result<(), i32> {
+            self.opens.fetch_add(1, Ordering::Relaxed);
+            self.result
+        }
+    }
+
+    /// Held by tests using the global logger, since there is only one per process.
+    static GLOBAL_LOGGER: spin::Mutex<()> = spin::Mutex::new(());
+
+    /// Install `config` as the global logger's configuration, whether or not it's initialised.
+    fn install(config: PspLoggerConfig) {
+        match PspLogger::init(config.clone()) {
+            Err(PspLoggerError::AlreadyInitialised) => PspLogger::reconfigure(|_| config).unwrap(),
+            result => result.unwrap(),
+        }
+    }
+
+    fn record(config: &PspLoggerConfig, level: Level, msg: &str) {
+        write_record(
+            config,
+            &Record::builder()
+                .level(level)
+                .args(format_args!("{}", msg))
+                .build(),
+        );
+    }
+
+    const LEVELS: [Level; 5] = [
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ];
+
+    fn write(fd: host::SceUid, data: &str) -> RecordedWrite {
+        RecordedWrite {
+            fd,
+            data: data.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn all_levels_default_to_stderr() {
+        let config = PspLoggerConfig::new(LevelFilter::Trace);
+
+        for level in LEVELS {
+            assert_eq!(config.get_stream(level), OutputStream::StdErr);
+        }
+    }
+
+    #[test]
+    fn each_level_can_be_mapped_independently() {
+        let config = PspLoggerConfig::new(LevelFilter::Trace)
+            .with_error_stream(OutputStream::StdOut)
+            .with_info_stream(OutputStream::StdOut)
+            .with_trace_stream(OutputStream::StdOut);
+
+        assert_eq!(config.get_stream(Level::Error), OutputStream::StdOut);
+        assert_eq!(config.get_stream(Level::Warn), OutputStream::StdErr);
+        assert_eq!(config.get_stream(Level::Info), OutputStream::StdOut);
+        assert_eq!(config.get_stream(Level::Debug), OutputStream::StdErr);
+        assert_eq!(config.get_stream(Level::Trace), OutputStream::StdOut);
+    }
+
+    #[test]
+    fn enabled_respects_level_filter() {
+        let config = PspLoggerConfig::new(LevelFilter::Info);
+
+        let enabled: Vec<bool> = LEVELS
+            .iter()
+            .map(|&level| config.enabled(&Metadata::builder().level(level).build()))
+            .collect();
+
+        assert_eq!(enabled, [true, true, true, false, false]);
+    }
+
+    #[test]
+    fn level_filter_off_disables_everything() {
+        let config = PspLoggerConfig::new(LevelFilter::Off);
+
+        for level in LEVELS {
+            assert!(!config.enabled(&Metadata::builder().level(level).build()));
+        }
+    }
+
+    #[test]
+    fn directives_override_level_filter_by_target() {
+        let config = PspLoggerConfig::new(LevelFilter::Info)
+            .with_directives(Directives::parse("warn,game::render=trace,game::audio=off").unwrap());
+        let enabled = |target, level| {
+            config.enabled(&Metadata::builder().target(target).level(level).build())
+        };
+
+        assert!(!enabled("game", Level::Info));
+        assert!(enabled("game", Level::Warn));
+        assert!(enabled("game::render::mesh", Level::Trace));
+        assert!(!enabled("game::audio", Level::Error));
+        assert_eq!(config.max_level(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn record_is_written_as_single_line() {
+        let config =
+            PspLoggerConfig::new(LevelFilter::Trace).with_info_stream(OutputStream::StdOut);
+
+        let writes = host::capture(|| {
+            write_record(
+                &config,
+                &Record::builder()
+                    .level(Level::Info)
+                    .args(format_args!("answer = {}", 42))
+                    .build(),
+            );
+            write_record(
+                &config,
+                &Record::builder()
+                    .level(Level::Warn)
+                    .args(format_args!("careful"))
+                    .build(),
+            );
+        });
+
+        assert_eq!(
+            writes,
+            [
+                write(STDOUT_FD, "answer = 42\n"),
+                write(STDERR_FD, "careful\n")
+            ]
+        );
+    }
+
+    #[test]
+    fn with_sink_replaces_stdio_for_all_levels() {
+        let sink = MemorySink::leak();
+        let config = PspLoggerConfig::new(LevelFilter::Trace)
+            .with_trace_stream(OutputStream::StdOut)
+            .with_sink(sink);
+
+        let writes = host::capture(|| {
+            record(&config, Level::Error, "error");
+            record(&config, Level::Trace, "trace");
+        });
+
+        assert!(writes.is_empty());
+        assert_eq!(
+            sink.lines(),
+            [
+                (OutputStream::StdErr, "error\n".to_string()),
+                (OutputStream::StdOut, "trace\n".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn levels_fan_out_to_their_destinations() {
+        let ring = MemorySink::leak();
+        let file = MemorySink::leak();
+        let config = PspLoggerConfig::new(LevelFilter::Trace)
+            .with_error_destinations(Destinations::new().with(&StdioSink).with(ring).with(file))
+            .with_warn_destinations(Destinations::new())
+            .with_trace_destinations(Destinations::new().with(ring));
+
+        let writes = host::capture(|| {
+            record(&config, Level::Error, "error");
+            record(&config, Level::Warn, "warn");
+            record(&config, Level::Info, "info");
+            record(&config, Level::Trace, "trace");
+        });
+
+        assert_eq!(
+            writes,
+            [write(STDERR_FD, "error\n"), write(STDERR_FD, "info\n")]
+        );
+        assert_eq!(
+            ring.lines(),
+            [
+                (OutputStream::StdErr, "error\n".to_string()),
+                (OutputStream::StdErr, "trace\n".to_string())
+            ]
+        );
+        assert_eq!(
+            file.lines(),
+            [(OutputStream::StdErr, "error\n".to_string())]
+        );
+    }
+
+    #[test]
+    fn flush_reaches_each_sink_once() {
+        let shared = MemorySink::leak();
+        let trace_only = MemorySink::leak();
+        let config = PspLoggerConfig::new(LevelFilter::Trace)
+            .with_sink(shared)
+            .with_trace_destinations(Destinations::new().with(shared).with(trace_only));
+
+        config.flush();
+
+        assert_eq!(shared.flushes.load(Ordering::Relaxed), 1);
+        assert_eq!(trace_only.flushes.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "too many destinations")]
+    fn destinations_are_bounded() {
+        let sink = MemorySink::leak();
+        let mut destinations = Destinations::new();
+
+        for _ in 0..=MAX_DESTINATIONS {
+            destinations = destinations.with(sink);
+        }
+    }
+
+    #[test]
+    fn records_use_configured_format() {
+        let sink = MemorySink::leak();
+        let config = PspLoggerConfig::new(LevelFilter::Trace)
+            .with_sink(sink)
+            .with_format("{level:5} [{target}] {msg}");
+
+        write_record(
+            &config,
+            &Record::builder()
+                .level(Level::Info)
+                .target("game")
+                .args(format_args!("loaded {} levels", 3))
+                .build(),
+        );
+
+        assert_eq!(
+            sink.lines(),
+            [(
+                OutputStream::StdErr,
+                "INFO  [game] loaded 3 levels\n".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn colors_only_reach_sinks_that_support_them() {
+        let plain = MemorySink::leak();
+        let config = PspLoggerConfig::new(LevelFilter::Trace)
+            .with_sink(plain)
+            .with_error_destinations(Destinations::new().with(&StdioSink).with(plain))
+            .with_colors(LevelColors::default().with_warn(Color::None));
+
+        let writes = host::capture(|| {
+            record(&config, Level::Error, "error");
+            record(&config, Level::Warn, "warn");
+        });
+
+        assert_eq!(writes, [write(STDERR_FD, "\x1b[31merror\x1b[0m\n")]);
+        assert_eq!(
+            plain.lines(),
+            [
+                (OutputStream::StdErr, "error\n".to_string()),
+                (OutputStream::StdErr, "warn\n".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn thread_prefix_comes_before_format() {
+        let sink = MemorySink::leak();
+        let config = PspLoggerConfig::new(LevelFilter::Trace)
+            .with_sink(sink)
+            .with_format("{level} {msg}")
+            .with_thread_info(ThreadInfo::IdAndName);
+
+        host::with_thread(0x0badc0de, "audio", || {
+            record(&config, Level::Info, "buffer underrun")
+        });
+
+        assert_eq!(
+            sink.lines(),
+            [(
+                OutputStream::StdErr,
+                "[0x0badc0de audio] INFO buffer underrun\n".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn records_at_flush_level_flush_their_sinks() {
+        let errors = MemorySink::leak();
+        let others = MemorySink::leak();
+        let config = PspLoggerConfig::new(LevelFilter::Trace)
+            .with_sink(others)
+            .with_error_destinations(Destinations::new().with(errors))
+            .with_warn_destinations(Destinations::new().with(errors));
+
+        record(&config, Level::Info, "info");
+        record(&config, Level::Warn, "warn");
+        assert_eq!(errors.flushes.load(Ordering::Relaxed), 0);
+
+        record(&config, Level::Error, "error");
+        assert_eq!(errors.flushes.load(Ordering::Relaxed), 1);
+        assert_eq!(others.flushes.load(Ordering::Relaxed), 0);
+
+        let config = config.with_flush_level(LevelFilter::Off);
+        record(&config, Level::Error, "error");
+        assert_eq!(errors.flushes.load(Ordering::Relaxed), 1);
+    }
+
+    /// Sink noting whether two threads ever write to it at the same time.
+    #[derive(Default)]
+    struct OverlapSink {
+        writing: AtomicBool,
+        overlaps: AtomicUsize,
+        writes: AtomicUsize,
+    }
+
+    impl LogSink for OverlapSink {
+        fn write(&self, _stream: OutputStream, _line: &str) {
+            if self.writing.swap(true, Ordering::SeqCst) {
+                self.overlaps.fetch_add(1, Ordering::SeqCst);
+            }
+
+            for _ in 0..10 {
+                std::thread::yield_now();
+            }
+
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            self.writing.store(false, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn records_from_different_threads_do_not_overlap() {
+        let sink: &'static OverlapSink = Box::leak(Box::default());
+        let config = PspLoggerConfig::new(LevelFilter::Trace).with_sink(sink);
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..25 {
+                        record(&config, Level::Info, "from a thread");
+                    }
+                });
+            }
+        });
+
+        assert_eq!(sink.writes.load(Ordering::SeqCst), 100);
+        assert_eq!(sink.overlaps.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn overlong_records_are_truncated() {
+        let sink = MemorySink::leak();
+        let config = PspLoggerConfig::new(LevelFilter::Trace).with_sink(sink);
+        let msg = "x".repeat(MAX_LINE_LEN * 2);
+
+        record(&config, Level::Info, &msg);
+
+        let (_, line) = &sink.lines()[0];
+        assert_eq!(line.len(), MAX_LINE_LEN);
+        assert!(line.ends_with("x...\n"));
+    }
+
+    #[test]
+    fn global_logger_filters_and_routes() {
+        let _guard = GLOBAL_LOGGER.lock();
+        let config =
+            PspLoggerConfig::new(LevelFilter::Debug).with_debug_stream(OutputStream::StdOut);
+        install(config);
+
+        let writes = host::capture(|| {
+            log::trace!("filtered");
+            log::debug!("debug");
+            log::error!("error");
+        });
+
+        assert_eq!(
+            writes,
+            [write(STDOUT_FD, "debug\n"), write(STDERR_FD, "error\n")]
+        );
+        assert!(!log::logger().enabled(&Metadata::builder().level(Level::Trace).build()));
+    }
+
+    #[test]
+    fn settings_file_falls_back_to_programmatic_config() {
+        host::add_file("ms0:/invalid.cfg", b"level = debug\nlevel = loud\n");
+        let config = PspLoggerConfig::new(LevelFilter::Warn);
+
+        let (loaded, error) = load_settings(config.clone(), "ms0:/invalid.cfg", &[]);
+        assert_eq!(loaded.level_filter, LevelFilter::Warn);
+        assert_eq!(
+            error,
+            Some(ConfigError::Invalid {
+                line: 2,
+                kind: ConfigErrorKind::InvalidLevel
+            })
+        );
+
+        let (loaded, error) = load_settings(config, "ms0:/missing.cfg", &[]);
+        assert_eq!(loaded.level_filter, LevelFilter::Warn);
+        assert_eq!(error, None);
+    }
+
+    #[test]
+    fn ignored_settings_file_is_reported_on_stderr() {
+        let error = ConfigError::Invalid {
+            line: 2,
+            kind: ConfigErrorKind::InvalidLevel,
+        };
+
+        let writes = host::capture(|| warn_settings_ignored("ms0:/invalid.cfg", error));
+
+        assert_eq!(
+            writes,
+            [write(
+                STDERR_FD,
+                &format!("psp_logger: ignoring ms0:/invalid.cfg: {}\n", error)
+            )]
+        );
+    }
+
+    #[test]
+    fn text_segment_is_logged_at_info() {
+        let sink = MemorySink::leak();
+        let config = PspLoggerConfig::new(LevelFilter::Info).with_sink(sink);
+
+        log_text_segment(&config);
+
+        assert_eq!(
+            sink.lines(),
+            [(
+                OutputStream::StdErr,
+                "module text at 0x08804000, size 0x40000\n".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn text_segment_respects_level_filter_and_directives() {
+        let sink = MemorySink::leak();
+        let quiet = PspLoggerConfig::new(LevelFilter::Off).with_sink(sink);
+        log_text_segment(&quiet);
+        assert_eq!(sink.lines(), []);
+
+        let directives = Directives::parse("error,psp_logger=info").unwrap();
+        let enabled = PspLoggerConfig::new(LevelFilter::Error)
+            .with_sink(sink)
+            .with_directives(directives);
+        log_text_segment(&enabled);
+        assert_eq!(sink.lines().len(), 1);
+    }
+
+    /// Formats as `loud`, logging another record while doing so.
+    struct Loud;
+
+    impl core::fmt::Display for Loud {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            log::warn!("deeper");
+            write!(f, "loud")
+        }
+    }
+
+    /// Sink logging a record every time it's written to.
+    struct ReentrantSink(MemorySink);
+
+    impl LogSink for ReentrantSink {
+        fn write(&self, stream: OutputStream, line: &str) {
+            self.0.write(stream, line);
+            log::warn!("{}", Loud);
+        }
+    }
+
+    #[test]
+    fn nested_records_skip_the_sinks() {
+        let _guard = GLOBAL_LOGGER.lock();
+        let sink: &'static ReentrantSink =
+            Box::leak(Box::new(ReentrantSink(MemorySink::default())));
+        install(PspLoggerConfig::new(LevelFilter::Trace).with_sink(sink));
+
+        let dropped = PspLogger::nested_records_dropped();
+        let writes = host::capture(|| log::error!("outer"));
+
+        assert_eq!(
+            sink.0.lines(),
+            [(OutputStream::StdErr, "outer\n".to_string())]
+        );
+        assert_eq!(writes, [write(STDERR_FD, "loud\n")]);
+        assert_eq!(PspLogger::nested_records_dropped(), dropped + 1);
+    }
+
+    #[test]
+    fn panics_while_logging_skip_the_sinks() {
+        let _guard = GLOBAL_LOGGER.lock();
+        let sink = MemorySink::leak();
+        install(PspLoggerConfig::new(LevelFilter::Trace).with_sink(sink));
+
+        let writes = host::with_thread(0x0badf00d, "panicky", || {
+            let _logging = reentrancy::enter();
+            host::capture(|| log_panic_message(&"oops", None))
+        });
+
+        assert!(sink.lines().is_empty());
+        assert_eq!(writes, [write(STDERR_FD, "panicked: oops\n")]);
+    }
+
+    #[test]
+    fn interrupt_records_are_written_by_the_next_record() {
+        let _guard = GLOBAL_LOGGER.lock();
+        install(PspLoggerConfig::new(LevelFilter::Info).with_info_stream(OutputStream::StdOut));
+
+        let writes = host::capture(|| {
+            host::in_interrupt(|| {
+                log::info!("vblank");
+                log::debug!("filtered");
+            });
+        });
+        assert!(writes.is_empty());
+
+        let writes = host::capture(|| log::error!("after"));
+        assert_eq!(
+            writes,
+            [write(STDOUT_FD, "vblank\n"), write(STDERR_FD, "after\n")]
+        );
+    }
+
+    #[test]
+    fn binary_records_are_written_as_frames() {
+        let _guard = GLOBAL_LOGGER.lock();
+        install(PspLoggerConfig::new(LevelFilter::Info).with_info_stream(OutputStream::StdOut));
+
+        let writes = host::capture(|| {
+            log_binary!(Level::Info, "{} at {:#x}", "tick", 0x40u32);
+            log_binary!(Level::Debug, "filtered {}", 1u8);
+        });
+
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].fd, STDOUT_FD);
+
+        let (frame, len) = binary::Frame::parse(&writes[0].data).unwrap();
+        assert_eq!(len, writes[0].data.len());
+        assert_eq!(
+            frame.args().collect::<Vec<_>>(),
+            [
+                Ok(binary::ArgValue::Str("tick")),
+                Ok(binary::ArgValue::U32(0x40))
+            ]
+        );
+    }
+
+    #[test]
+    fn second_init_is_rejected() {
+        let _guard = GLOBAL_LOGGER.lock();
+        install(PspLoggerConfig::new(LevelFilter::Info));
+
+        assert_eq!(
+            PspLogger::init(PspLoggerConfig::new(LevelFilter::Trace)),
+            Err(PspLoggerError::AlreadyInitialised)
+        );
+        assert_eq!(log::max_level(), LevelFilter::Info);
+    }
+
+    #[test]
+    fn sinks_are_opened_once_each() {
+        let sink = OpenSink::leak(Ok(()));
+        let config = PspLoggerConfig::new(LevelFilter::Trace)
+            .with_sink(sink)
+            .with_error_destinations(Destinations::new().with(sink).with(&StdioSink));
+
+        assert_eq!(config.open_sinks(), Ok(()));
+        assert_eq!(sink.opens.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn sink_open_failure_is_reported() {
+        let _guard = GLOBAL_LOGGER.lock();
+        install(PspLoggerConfig::new(LevelFilter::Info));
+
+        let result = PspLogger::reconfigure(|config| {
+            config
+                .with_level_filter(LevelFilter::Trace)
+                .with_sink(OpenSink::leak(Err(-5)))
+        });
+
+        assert_eq!(result, Err(PspLoggerError::SinkOpen(-5)));
+        assert_eq!(log::max_level(), LevelFilter::Info);
+    }
+
+    #[test]
+    fn global_logger_can_be_reconfigured() {
+        let _guard = GLOBAL_LOGGER.lock();
+        install(PspLoggerConfig::new(LevelFilter::Info));
+
+        PspLogger::reconfigure(|config| {
+            config
+                .with_level_filter(LevelFilter::Trace)
+                .with_trace_stream(OutputStream::StdOut)
+        })
+        .unwrap();
+        assert_eq!(log::max_level(), LevelFilter::Trace);
+
+        let writes = host::capture(|| log::trace!("trace"));
+        assert_eq!(writes, [write(STDOUT_FD, "trace\n")]);
+
+        PspLogger::set_directives(Directives::parse("psp_logger=off").unwrap()).unwrap();
+        let writes = host::capture(|| log::error!("error"));
+        assert!(writes.is_empty());
+
+        PspLogger::set_directives(Directives::new()).unwrap();
+        PspLogger::set_level_filter(LevelFilter::Warn).unwrap();
+        assert_eq!(log::max_level(), LevelFilter::Warn);
+        assert!(!log::logger().enabled(&Metadata::builder().level(Level::Info).build()));
+    }
+
+    /// Sink logging a record when it's opened.
+    struct ChattySink;
+
+    impl LogSink for ChattySink {
+        fn write(&self, _stream: OutputStream, _line: &str) {}
+
+        fn open(&self) -> Result<(), i32> {
+            log::warn!("opening");
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sinks_can_log_while_being_opened() {
+        let _guard = GLOBAL_LOGGER.lock();
+        let sink = MemorySink::leak();
+        install(PspLoggerConfig::new(LevelFilter::Info).with_sink(sink));
+
+        PspLogger::reconfigure(|config| {
+            config.with_warn_destinations(Destinations::new().with(sink).with(&ChattySink))
+        })
+        .unwrap();
+
+        assert_eq!(
+            sink.lines().last(),
+            Some(&(OutputStream::StdErr, "opening\n".to_string()))
+        );
+    }
 }
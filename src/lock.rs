@@ -0,0 +1,147 @@
+//! Locks that sleep while waiting, including the one keeping each record's output together when
+//! several threads log at once.
+
+use crate::sys::*;
+
+/// How long a thread waits between attempts to take a [SleepLock], in microseconds.
+const RETRY_DELAY_US: u32 = 100;
+
+/// Lock that puts waiting threads to sleep between attempts to take it.
+///
+/// The PSP never runs a lower priority thread while a higher priority one is ready, so a plain
+/// spin lock held by a low priority thread would spin forever. Sleeping lets the holder finish.
+/// Locks held while calling a blocking syscall, such as `sceIoWrite`, must be of this kind.
+pub(crate) struct SleepLock<T = ()> {
+    locked: spin::Mutex<T>,
+}
+
+pub(crate) type SleepLockGuard<'a, T = ()> = spin::MutexGuard<'a, T>;
+
+impl SleepLock {
+    pub(crate) const fn new() -> Self {
+        Self::with_value(())
+    }
+}
+
+impl<T> SleepLock<T> {
+    /// Construct a lock protecting `value`.
+    pub(crate) const fn with_value(value: T) -> Self {
+        SleepLock {
+            locked: spin::Mutex::new(value),
+        }
+    }
+
+    /// Take the lock, waiting as long as it takes.
+    pub(crate) fn lock(&self) -> SleepLockGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.locked.try_lock() {
+                return guard;
+            }
+
+            unsafe {
+                sceKernelDelayThread(RETRY_DELAY_US);
+            }
+        }
+    }
+
+    /// Take the lock, giving up after `attempts` tries.
+    pub(crate) fn lock_with_retries(&self, attempts: u32) -> Option<SleepLockGuard<'_, T>> {
+        for attempt in 0..attempts {
+            if attempt > 0 {
+                unsafe {
+                    sceKernelDelayThread(RETRY_DELAY_US);
+                }
+            }
+
+            if let Some(guard) = self.locked.try_lock() {
+                return Some(guard);
+            }
+        }
+
+        None
+    }
+}
+
+/// Readers-writer lock that puts waiting threads to sleep between attempts to take it.
+///
+/// See [SleepLock] for why this doesn't simply spin. Writers wait until no reader holds the lock.
+pub(crate) struct SleepRwLock<T> {
+    lock: spin::RwLock<T>,
+}
+
+impl<T> SleepRwLock<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        SleepRwLock {
+            lock: spin::RwLock::new(value),
+        }
+    }
+
+    /// Take the lock for reading, waiting as long as it takes.
+    pub(crate) fn read(&self) -> spin::RwLockReadGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.lock.try_read() {
+                return guard;
+            }
+
+            unsafe {
+                sceKernelDelayThread(RETRY_DELAY_US);
+            }
+        }
+    }
+
+    /// Take the lock for reading if nothing is writing.
+    pub(crate) fn try_read(&self) -> Option<spin::RwLockReadGuard<'_, T>> {
+        self.lock.try_read()
+    }
+
+    /// Take the lock for writing, waiting as long as it takes.
+    pub(crate) fn write(&self) -> spin::RwLockWriteGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.lock.try_write() {
+                return guard;
+            }
+
+            unsafe {
+                sceKernelDelayThread(RETRY_DELAY_US);
+            }
+        }
+    }
+}
+
+/// Held while a record is written to its sinks.
+pub(crate) static RECORD_LOCK: SleepLock = SleepLock::new();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_with_retries_gives_up() {
+        let lock = SleepLock::new();
+
+        let guard = lock.lock();
+        assert!(lock.lock_with_retries(3).is_none());
+
+        drop(guard);
+        assert!(lock.lock_with_retries(3).is_some());
+    }
+
+    #[test]
+    fn readers_share_and_writers_wait() {
+        let lock = SleepRwLock::new(1);
+
+        let first = lock.read();
+        let second = lock.read();
+        assert_eq!(*first + *second, 2);
+
+        std::thread::scope(|scope| {
+            let writer = scope.spawn(|| *lock.write() = 2);
+
+            drop(first);
+            drop(second);
+            writer.join().unwrap();
+        });
+
+        assert_eq!(*lock.try_read().unwrap(), 2);
+    }
+}
@@ -0,0 +1,166 @@
+//! Information about the module the logger is linked into.
+
+use core::fmt::{self, Display};
+use core::mem::MaybeUninit;
+use core::ptr::{addr_of, addr_of_mut};
+
+use crate::sys::*;
+
+/// Start of the line the logger writes when it's initialised. See the [Display] impl.
+const MARKER: &str = "module text at ";
+
+/// Most loaded modules searched for the one the logger is linked into.
+const MAX_MODULES: usize = 64;
+
+/// Where a module's code was loaded.
+///
+/// PRX modules are relocated when they are loaded, so the addresses in crash reports and
+/// backtraces only make sense alongside the address their text segment was loaded at. The logger
+/// writes this in a line when it is initialised, and in crash dumps, for the `psp-symbolize` tool
+/// in this repository to use.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TextSegment {
+    /// Address the first byte of the text segment was loaded at.
+    pub addr: u32,
+
+    /// Size of the text segment in bytes.
+    pub size: u32,
+}
+
+impl TextSegment {
+    /// Whether `addr` is inside the text segment.
+    pub fn contains(&self, addr: u32) -> bool {
+        addr.wrapping_sub(self.addr) < self.size
+    }
+
+    /// Find the text segment in a log line written by the logger at startup.
+    ///
+    /// The line can have anything before the message, such as a timestamp or thread name from
+    /// the configured format. Returns `None` for any other line.
+    pub fn from_log_line(line: &str) -> Option<Self> {
+        let rest = &line[line.find(MARKER)? + MARKER.len()..];
+        let (addr, rest) = parse_hex(rest)?;
+        let (size, _) = parse_hex(rest.strip_prefix(", size ")?)?;
+
+        Some(TextSegment { addr, size })
+    }
+}
+
+/// Parse a `0x` prefixed hex number from the start of `s`, returning it and the rest of `s`.
+fn parse_hex(s: &str) -> Option<(u32, &str)> {
+    let digits = s.strip_prefix("0x")?;
+    let len = digits
+        .find(|c: char| !c.is_ascii_hexdigit())
+        .unwrap_or(digits.len());
+
+    let value = u32::from_str_radix(&digits[..len], 16).ok()?;
+    Some((value, &digits[len..]))
+}
+
+/// The text segment of the module the logger is linked into, or `None` if the kernel can't be
+/// asked.
+///
+/// The kernel has no call for the current module, so this looks through the loaded modules for
+/// the one whose text segment holds the logger's code.
+pub(crate) fn current_text_segment() -> Option<TextSegment> {
+    let mut ids = [SceUid(0); MAX_MODULES];
+    let mut count = 0;
+
+    if unsafe { sceKernelGetModuleIdList(ids.as_mut_ptr(), MAX_MODULES as i32, &mut count) } < 0 {
+        return None;
+    }
+
+    let code = code_address();
+    ids[..(count.max(0) as usize).min(MAX_MODULES)]
+        .iter()
+        .filter_map(|&id| text_segment(id))
+        .find(|text| text.contains(code))
+}
+
+/// The text segment of module `id`, or `None` if it can't be queried, e.g. from user mode.
+fn text_segment(id: SceUid) -> Option<TextSegment> {
+    let mut info = MaybeUninit::<SceKernelModuleInfo>::zeroed();
+    let info = info.as_mut_ptr();
+
+    unsafe {
+        addr_of_mut!((*info).size).write(core::mem::size_of::<SceKernelModuleInfo>());
+
+        if sceKernelQueryModuleInfo(id, info) < 0 {
+            return None;
+        }
+
+        Some(TextSegment {
+            addr: addr_of!((*info).text_addr).read(),
+            size: addr_of!((*info).text_size).read(),
+        })
+    }
+}
+
+/// Writes the message logged at startup, e.g. `module text at 0x08804000, size 0x1a2b0`.
+///
+/// The host tools look for this text with [TextSegment::from_log_line], so it must not change.
+impl Display for TextSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "module text at {:#010x}, size {:#x}",
+            self.addr, self.size
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host;
+    use std::string::ToString;
+
+    #[test]
+    fn text_segment_is_found_among_loaded_modules() {
+        assert_eq!(
+            current_text_segment(),
+            Some(TextSegment {
+                addr: host::MODULE_TEXT_ADDR,
+                size: host::MODULE_TEXT_SIZE,
+            })
+        );
+    }
+
+    #[test]
+    fn contains() {
+        let text = TextSegment {
+            addr: 0x0880_4000,
+            size: 0x100,
+        };
+
+        assert!(!text.contains(0x0880_3ffc));
+        assert!(text.contains(0x0880_4000));
+        assert!(text.contains(0x0880_40fc));
+        assert!(!text.contains(0x0880_4100));
+    }
+
+    #[test]
+    fn display() {
+        let text = TextSegment {
+            addr: 0x0880_4000,
+            size: 0x1a2b0,
+        };
+
+        assert_eq!(text.to_string(), "module text at 0x08804000, size 0x1a2b0");
+    }
+
+    #[test]
+    fn from_log_line() {
+        assert_eq!(
+            TextSegment::from_log_line("[0x1] INFO module text at 0x08804000, size 0x1a2b0\n"),
+            Some(TextSegment {
+                addr: 0x0880_4000,
+                size: 0x1a2b0
+            })
+        );
+        assert_eq!(
+            TextSegment::from_log_line("module text at 0x08804000\n"),
+            None
+        );
+    }
+}
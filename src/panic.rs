@@ -0,0 +1,162 @@
+//! Logging of panics through the logger.
+
+use core::fmt::Display;
+use core::panic::{Location, PanicInfo};
+
+use log::{Level, Record};
+
+use crate::lock::RECORD_LOCK;
+use crate::reentrancy;
+use crate::{write_record_with_lock, PspLoggerConfig, LOGGER_CONF};
+
+/// Target of the records written for panics.
+const PANIC_TARGET: &str = "panic";
+
+/// Attempts made to take the record lock before writing a panic without it.
+///
+/// The panic may have happened while this thread was writing a record, in which case the lock
+/// will never be released.
+const LOCK_ATTEMPTS: u32 = 100;
+
+/// Log a panic at error level, then flush every sink.
+///
+/// Panics are logged even if the error level is filtered out, so that they always reach the
+/// memory stick log or ring buffer. If the logger hasn't been initialised, or the panic happened
+/// while it was being reconfigured or while the thread was logging, the panic is written straight
+/// to stderr instead.
+///
+/// This is opt-in: call it from the application's panic handler before halting.
+///
+/// # Examples
+/// ```ignore
+/// #[panic_handler]
+/// fn panic(info: &core::panic::PanicInfo) -> ! {
+///     psp_logger::log_panic(info);
+///
+///     loop {}
+/// }
+/// ```
+pub fn log_panic(info: &PanicInfo) {
+    log_panic_message(&info.message(), info.location());
+}
+
+/// Log a panic from its message and location, then flush every sink.
+///
+/// This is the same as [log_panic], for panic hooks that aren't given a
+/// [PanicInfo](core::panic::PanicInfo), such as those set with `std::panic::set_hook`.
+pub fn log_panic_message(message: &dyn Display, location: Option<&Location>) {
+    let entry = reentrancy::enter();
+
+    // A panic while logging, e.g. in a sink, may have left the sinks part way through a write.
+    if entry.depth() > 0 {
+        write_fallback(message, location);
+        return;
+    }
+
+    match LOGGER_CONF.try_read() {
+        Some(config) => match config.as_ref() {
+            Some(config) => write_panic(config, message, location),
+            None => write_fallback(message, location),
+        },
+        None => write_fallback(message, location),
+    }
+}
+
+fn write_panic(config: &PspLoggerConfig, message: &dyn Display, location: Option<&Location>) {
+    let lock = || RECORD_LOCK.lock_with_retries(LOCK_ATTEMPTS);
+
+    match location {
+        Some(location) => write_record_with_lock(
+            config,
+            &Record::builder()
+                .level(Level::Error)
+                .target(PANIC_TARGET)
+                .file(Some(location.file()))
+                .line(Some(location.line()))
+                .args(format_args!("panicked at {}: {}", location, message))
+                .build(),
+            lock,
+        ),
+        None => write_record_with_lock(
+            config,
+            &Record::builder()
+                .level(Level::Error)
+                .target(PANIC_TARGET)
+                .args(format_args!("panicked: {}", message))
+                .build(),
+            lock,
+        ),
+    }
+
+    config.flush();
+}
+
+/// Write the panic to stderr without going through the logger's configuration.
+fn write_fallback(message: &dyn Display, location: Option<&Location>) {
+    let config = PspLoggerConfig::new(log::LevelFilter::Error);
+    write_panic(&config, message, location);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::{self, STDERR_FD};
+    use crate::RingBuffer;
+    use std::boxed::Box;
+    use std::string::{String, ToString};
+
+    fn lines(ring: &RingBuffer<4>) -> Vec<String> {
+        let mut lines = Vec::new();
+        ring.for_each(|line| lines.push(line.to_string()));
+        lines
+    }
+
+    #[test]
+    fn panic_is_logged_with_location() {
+        let ring: &'static RingBuffer<4> = Box::leak(Box::new(RingBuffer::new()));
+        let config = PspLoggerConfig::new(log::LevelFilter::Off)
+            .with_format("{level} [{target}] {msg}")
+            .with_sink(ring);
+
+        let location = Location::caller();
+        write_panic(&config, &"index out of bounds", Some(location));
+
+        let expected = std::format!(
+            "ERROR [panic] panicked at {}:{}:{}: index out of bounds",
+            location.file(),
+            location.line(),
+            location.column()
+        );
+        assert_eq!(lines(ring), [expected]);
+    }
+
+    #[test]
+    fn panic_without_location() {
+        let ring: &'static RingBuffer<4> = Box::leak(Box::new(RingBuffer::new()));
+        let config = PspLoggerConfig::new(log::LevelFilter::Error).with_sink(ring);
+
+        write_panic(&config, &"oops", None);
+
+        assert_eq!(lines(ring), ["panicked: oops"]);
+    }
+
+    #[test]
+    fn panic_is_logged_while_record_lock_is_held() {
+        let ring: &'static RingBuffer<4> = Box::leak(Box::new(RingBuffer::new()));
+        let config = PspLoggerConfig::new(log::LevelFilter::Error).with_sink(ring);
+
+        let _guard = RECORD_LOCK.lock();
+        write_panic(&config, &"oops", None);
+
+        assert_eq!(lines(ring), ["panicked: oops"]);
+    }
+
+    #[test]
+    fn fallback_writes_to_stderr() {
+        let writes = host::capture(|| write_fallback(&"oops", None));
+
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].fd, STDERR_FD);
+        assert_eq!(writes[0].data, b"panicked: oops\n");
+    }
+}
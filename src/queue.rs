@@ -0,0 +1,242 @@
+//! Bounded lock-free queue of log lines.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::OutputStream;
+
+/// A line waiting in a [LineQueue].
+struct Slot<const LINE_LEN: usize> {
+    /// Which lap of the queue this slot is on, and whether it currently holds a line.
+    ///
+    /// A slot at index `i` is free for the producer claiming position `pos` when this equals
+    /// `pos - i`, and full for the consumer claiming `pos` when it equals `pos - i + 1`.
+    sequence: AtomicUsize,
+    stream: UnsafeCell<OutputStream>,
+    len: UnsafeCell<usize>,
+    line: UnsafeCell<[u8; LINE_LEN]>,
+}
+
+/// Multi-producer, multi-consumer bounded queue of lines, after Dmitry Vyukov's design.
+///
+/// Pushing and popping never lock, so a thread holding a slot can't block other threads
+/// indefinitely. `SLOTS` must be a power of two.
+pub(crate) struct LineQueue<const SLOTS: usize, const LINE_LEN: usize> {
+    slots: [Slot<LINE_LEN>; SLOTS],
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+// Access to each slot's contents is arbitrated by its sequence number.
+unsafe impl<const SLOTS: usize, const LINE_LEN: usize> Sync for LineQueue<SLOTS, LINE_LEN> {}
+
+impl<const LINE_LEN: usize> Slot<LINE_LEN> {
+    const fn new() -> Self {
+        Slot {
+            sequence: AtomicUsize::new(0),
+            stream: UnsafeCell::new(OutputStream::StdErr),
+            len: UnsafeCell::new(0),
+            line: UnsafeCell::new([0; LINE_LEN]),
+        }
+    }
+}
+
+impl<const SLOTS: usize, const LINE_LEN: usize> LineQueue<SLOTS, LINE_LEN> {
+    const MASK: usize = SLOTS - 1;
+
+    pub(crate) const fn new() -> Self {
+        assert!(SLOTS.is_power_of_two(), "queue size must be a power of two");
+        assert!(LINE_LEN > 0, "queue lines must hold at least a newline");
+
+        LineQueue {
+            slots: [const { Slot::new() }; SLOTS],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Add a line to the back of the queue.
+    ///
+    /// Lines longer than `LINE_LEN` are truncated, keeping their trailing newline.
+    ///
+    /// Returns `false` if the queue is full.
+    pub(crate) fn push(&self, stream: OutputStream, line: &str) -> bool {
+        let mut pos = self.tail.load(Ordering::Relaxed);
+
+        loop {
+            let slot = &self.slots[pos & Self::MASK];
+            let lap = pos & !Self::MASK;
+            let sequence = slot.sequence.load(Ordering::Acquire);
+
+            match sequence.wrapping_sub(lap) as isize {
+                0 => match self.tail.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { Self::fill(slot, stream, line) };
+                        slot.sequence.store(lap.wrapping_add(1), Ordering::Release);
+                        return true;
+                    }
+                    Err(current) => pos = current,
+                },
+                // The slot still holds a line from the previous lap.
+                diff if diff < 0 => return false,
+                _ => pos = self.tail.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    /// Remove the line at the front of the queue, passing it to `f`.
+    ///
+    /// Returns `false` if the queue is empty.
+    pub(crate) fn pop<F: FnOnce(OutputStream, &str)>(&self, f: F) -> bool {
+        let mut pos = self.head.load(Ordering::Relaxed);
+
+        loop {
+            let slot = &self.slots[pos & Self::MASK];
+            let lap = pos & !Self::MASK;
+            let sequence = slot.sequence.load(Ordering::Acquire);
+
+            match sequence.wrapping_sub(lap.wrapping_add(1)) as isize {
+                0 => match self.head.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe {
+                            let buf = &*slot.line.get();
+                            let line = &buf[..*slot.len.get()];
+                            f(*slot.stream.get(), core::str::from_utf8_unchecked(line));
+                        }
+                        slot.sequence
+                            .store(lap.wrapping_add(SLOTS), Ordering::Release);
+                        return true;
+                    }
+                    Err(current) => pos = current,
+                },
+                // The slot hasn't been filled on this lap yet.
+                diff if diff < 0 => return false,
+                _ => pos = self.head.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    /// Copy a line into a slot that the caller has exclusively claimed.
+    unsafe fn fill(slot: &Slot<LINE_LEN>, stream: OutputStream, line: &str) {
+        let buf = &mut *slot.line.get();
+
+        let len = if line.len() <= LINE_LEN {
+            buf[..line.len()].copy_from_slice(line.as_bytes());
+            line.len()
+        } else {
+            let mut end = LINE_LEN - 1;
+            while !line.is_char_boundary(end) {
+                end -= 1;
+            }
+
+            buf[..end].copy_from_slice(&line.as_bytes()[..end]);
+            buf[end] = b'\n';
+            end + 1
+        };
+
+        *slot.stream.get() = stream;
+        *slot.len.get() = len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::{String, ToString};
+    use std::sync::Arc;
+    use std::vec::Vec;
+
+    fn pop<const SLOTS: usize, const LINE_LEN: usize>(
+        queue: &LineQueue<SLOTS, LINE_LEN>,
+    ) -> Option<(OutputStream, String)> {
+        let mut popped = None;
+        queue.pop(|stream, line| popped = Some((stream, line.to_string())));
+        popped
+    }
+
+    #[test]
+    fn fifo_until_full() {
+        let queue: LineQueue<2, 16> = LineQueue::new();
+
+        assert!(queue.push(OutputStream::StdOut, "one\n"));
+        assert!(queue.push(OutputStream::StdErr, "two\n"));
+        assert!(!queue.push(OutputStream::StdErr, "three\n"));
+
+        assert_eq!(
+            pop(&queue),
+            Some((OutputStream::StdOut, "one\n".to_string()))
+        );
+        assert!(queue.push(OutputStream::StdErr, "four\n"));
+        assert_eq!(
+            pop(&queue),
+            Some((OutputStream::StdErr, "two\n".to_string()))
+        );
+        assert_eq!(
+            pop(&queue),
+            Some((OutputStream::StdErr, "four\n".to_string()))
+        );
+        assert_eq!(pop(&queue), None);
+    }
+
+    #[test]
+    fn long_lines_keep_newline() {
+        let queue: LineQueue<1, 6> = LineQueue::new();
+
+        queue.push(OutputStream::StdErr, "abcd\u{e9}fg\n");
+
+        assert_eq!(
+            pop(&queue),
+            Some((OutputStream::StdErr, "abcd\n".to_string()))
+        );
+    }
+
+    #[test]
+    fn concurrent_producers_and_consumer() {
+        const PER_THREAD: usize = 500;
+        let queue: Arc<LineQueue<8, 16>> = Arc::new(LineQueue::new());
+
+        let producers: Vec<_> = (0..4)
+            .map(|thread| {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    for i in 0..PER_THREAD {
+                        let line = std::format!("{} {}\n", thread, i);
+                        while !queue.push(OutputStream::StdErr, &line) {
+                            std::thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let mut next = [0; 4];
+        let mut received = 0;
+        while received < 4 * PER_THREAD {
+            if let Some((_, line)) = pop(&queue) {
+                let mut parts = line.trim_end().split(' ');
+                let thread: usize = parts.next().unwrap().parse().unwrap();
+                let i: usize = parts.next().unwrap().parse().unwrap();
+
+                // Each producer's lines arrive in order.
+                assert_eq!(i, next[thread]);
+                next[thread] += 1;
+                received += 1;
+            }
+        }
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        assert_eq!(pop(&queue), None);
+    }
+}
@@ -0,0 +1,136 @@
+//! Detection of records logged by a thread that is already part way through logging one.
+//!
+//! This happens when a sink, a formatted argument or something they call, such as an allocation
+//! tracker, logs. Sending those records back through the full pipeline could recurse forever or
+//! wait on the record lock the thread already holds.
+
+use core::sync::atomic::{AtomicI32, AtomicU32, Ordering};
+
+use crate::thread;
+
+/// Most threads that can be tracked as logging at the same time.
+const MAX_THREADS: usize = 8;
+
+/// Depth given to threads that can't be tracked.
+///
+/// Whether they are already logging isn't known, so their records are dropped, as the deepest
+/// nested records are, rather than risk waiting on a record lock they hold.
+pub(crate) const UNTRACKED_DEPTH: u32 = u32::MAX;
+
+/// The threads currently logging a record, and how deeply nested each one is.
+pub(crate) struct LoggingThreads<const N: usize> {
+    ids: [AtomicI32; N],
+    depths: [AtomicU32; N],
+}
+
+/// A thread's place in [LoggingThreads], given up when dropped.
+pub(crate) struct Entry<'a> {
+    depth: u32,
+    slot: Option<(&'a AtomicI32, &'a AtomicU32)>,
+}
+
+impl<const N: usize> LoggingThreads<N> {
+    pub(crate) const fn new() -> Self {
+        LoggingThreads {
+            ids: [const { AtomicI32::new(0) }; N],
+            depths: [const { AtomicU32::new(0) }; N],
+        }
+    }
+
+    /// Note that the thread `id` has started logging a record.
+    ///
+    /// If too many threads are logging at once to track another, the thread is given
+    /// [UNTRACKED_DEPTH].
+    pub(crate) fn enter(&self, id: i32) -> Entry<'_> {
+        // Only the thread itself changes its own slot, so a match can't be taken away.
+        for (slot_id, depth) in self.ids.iter().zip(&self.depths) {
+            if slot_id.load(Ordering::Acquire) == id {
+                return Entry {
+                    depth: depth.fetch_add(1, Ordering::Relaxed) + 1,
+                    slot: Some((slot_id, depth)),
+                };
+            }
+        }
+
+        for (slot_id, depth) in self.ids.iter().zip(&self.depths) {
+            if slot_id
+                .compare_exchange(0, id, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                depth.store(0, Ordering::Relaxed);
+                return Entry {
+                    depth: 0,
+                    slot: Some((slot_id, depth)),
+                };
+            }
+        }
+
+        Entry {
+            depth: UNTRACKED_DEPTH,
+            slot: None,
+        }
+    }
+}
+
+impl Entry<'_> {
+    /// How many records the thread was already logging when this one started.
+    pub(crate) fn depth(&self) -> u32 {
+        self.depth
+    }
+}
+
+impl Drop for Entry<'_> {
+    fn drop(&mut self) {
+        if let Some((id, depth)) = self.slot {
+            if self.depth == 0 {
+                id.store(0, Ordering::Release);
+            } else {
+                depth.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+static LOGGING_THREADS: LoggingThreads<MAX_THREADS> = LoggingThreads::new();
+
+/// Note that the current thread has started logging a record.
+pub(crate) fn enter() -> Entry<'static> {
+    LOGGING_THREADS.enter(thread::current_id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nesting_is_tracked_per_thread() {
+        let threads = LoggingThreads::<2>::new();
+
+        let outer = threads.enter(7);
+        let other = threads.enter(9);
+        assert_eq!(outer.depth(), 0);
+        assert_eq!(other.depth(), 0);
+
+        {
+            let nested = threads.enter(7);
+            assert_eq!(nested.depth(), 1);
+            assert_eq!(threads.enter(7).depth(), 2);
+        }
+        assert_eq!(threads.enter(7).depth(), 1);
+
+        drop(outer);
+        assert_eq!(threads.enter(7).depth(), 0);
+    }
+
+    #[test]
+    fn untracked_threads_are_treated_as_deeply_nested() {
+        let threads = LoggingThreads::<1>::new();
+
+        let first = threads.enter(7);
+        assert_eq!(threads.enter(9).depth(), UNTRACKED_DEPTH);
+        assert_eq!(threads.enter(9).depth(), UNTRACKED_DEPTH);
+
+        drop(first);
+        assert_eq!(threads.enter(9).depth(), 0);
+    }
+}
@@ -0,0 +1,408 @@
+//! Sink handing lines to a background thread for writing.
+
+use core::ffi::c_void;
+use core::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+
+use crate::lock::SleepLock;
+use crate::queue::LineQueue;
+use crate::sys::*;
+use crate::thread;
+use crate::{LogSink, OutputStream, MAX_LINE_LEN};
+
+/// How long a blocked writer waits between checks for free space, and a flush between checks
+/// that the writer thread has caught up, in microseconds.
+const BLOCK_DELAY_US: u32 = 100;
+
+/// What an [AsyncSink] does with a line when its queue is full.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Discard the new line.
+    DropNewest,
+
+    /// Discard the oldest queued line to make room for the new one.
+    DropOldest,
+
+    /// Wait until the writer thread makes room.
+    ///
+    /// If the writer thread hasn't been started, the queue is drained on the logging thread
+    /// instead.
+    Block,
+}
+
+/// Sink that queues lines for another sink to write on a background thread.
+///
+/// Logging only costs a copy into a lock-free queue of `SLOTS` lines, each up to `LINE_LEN`
+/// bytes, rather than a blocking `sceIoWrite`. A writer thread, started with
+/// [start](Self::start), drains the queue into the inner sink. Only one thread writes queued
+/// lines to the inner sink at a time, so they reach it in the order they were queued.
+///
+/// When the queue is full, the [OverflowPolicy] decides which line is lost, and
+/// [dropped](Self::dropped) counts how many have been.
+///
+/// `SLOTS` must be a power of two.
+///
+/// # Examples
+/// ```
+/// use psp_logger::{AsyncSink, OverflowPolicy, PspLoggerConfig, StdioSink};
+///
+/// static SINK: AsyncSink<StdioSink, 64> = AsyncSink::new(StdioSink, OverflowPolicy::DropOldest);
+///
+/// // Writer thread with a low priority and an 8KiB stack.
+/// let _ = SINK.start(0x70, 0x2000);
+///
+/// let config = PspLoggerConfig::new(log::LevelFilter::Trace).with_sink(&SINK);
+/// ```
+pub struct AsyncSink<S: LogSink, const SLOTS: usize, const LINE_LEN: usize = MAX_LINE_LEN> {
+    inner: S,
+    policy: OverflowPolicy,
+    queue: LineQueue<SLOTS, LINE_LEN>,
+    dropped: AtomicUsize,
+    /// Id of the writer thread, or 0 if it hasn't been started.
+    thread: AtomicI32,
+    /// Lines queued so far.
+    queued: AtomicUsize,
+    /// Lines taken off the queue so far, whether written or discarded.
+    finished: AtomicUsize,
+    /// Held while writing queued lines to the inner sink.
+    draining: SleepLock,
+}
+
+impl<S: LogSink, const SLOTS: usize, const LINE_LEN: usize> AsyncSink<S, SLOTS, LINE_LEN> {
+    /// Construct an async sink writing to `inner`.
+    ///
+    /// Nothing is written until [start](Self::start) is called, or the sink is flushed.
+    pub const fn new(inner: S, policy: OverflowPolicy) -> Self {
+        AsyncSink {
+            inner,
+            policy,
+            queue: LineQueue::new(),
+            dropped: AtomicUsize::new(0),
+            thread: AtomicI32::new(0),
+            queued: AtomicUsize::new(0),
+            finished: AtomicUsize::new(0),
+            draining: SleepLock::new(),
+        }
+    }
+
+    /// Start the writer thread.
+    ///
+    /// Does nothing if it is already running.
+    ///
+    /// # Arguments
+    /// - `priority`: Priority of the writer thread. Larger numbers are lower priorities.
+    /// - `stack_size`: Stack size of the writer thread in bytes. It needs enough room for the
+    ///   inner sink to write a line.
+    ///
+    /// Returns the PSP error code if the thread couldn't be created or started.
+    pub fn start(&'static self, priority: i32, stack_size: i32) -> Result<(), i32> {
+        if self.thread.load(Ordering::Acquire) != 0 {
+            return Ok(());
+        }
+
+        let thread = thread::spawn(
+            c"psp_logger_writer",
+            writer_thread::<S, SLOTS, LINE_LEN>,
+            priority,
+            stack_size,
+            self,
+        )?;
+        self.thread.store(thread.0, Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Write every queued line to the inner sink on the calling thread.
+    ///
+    /// Waits for the writer thread first if it is part way through draining the queue.
+    ///
+    /// Returns the number of lines written.
+    pub fn drain(&self) -> usize {
+        let _guard = self.draining.lock();
+        let mut count = 0;
+
+        while self
+            .queue
+            .pop(|stream, line| self.inner.write(stream, line))
+        {
+            self.finished.fetch_add(1, Ordering::Release);
+            count += 1;
+        }
+
+        count
+    }
+
+    /// Number of lines lost because the queue was full.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn writer_running(&self) -> bool {
+        self.thread.load(Ordering::Acquire) != 0
+    }
+
+    /// Have every line queued so far written to the inner sink, by the writer thread if it is
+    /// running, or the calling thread if not.
+    fn write_queued(&self) {
+        let thread = self.thread.load(Ordering::Acquire);
+
+        if thread == 0 {
+            self.drain();
+        } else {
+            let queued = self.queued.load(Ordering::Acquire);
+            unsafe {
+                sceKernelWakeupThread(SceUid(thread));
+            }
+            self.wait_for_writer(queued);
+        }
+    }
+
+    /// Wait until the writer thread has taken every line queued before `queued` off the queue.
+    fn wait_for_writer(&self, queued: usize) {
+        while (self.finished.load(Ordering::Acquire).wrapping_sub(queued) as isize) < 0 {
+            unsafe {
+                sceKernelDelayThread(BLOCK_DELAY_US);
+            }
+        }
+    }
+}
+
+unsafe extern "C" fn writer_thread<S: LogSink, const SLOTS: usize, const LINE_LEN: usize>(
+    _args: usize,
+    argp: *mut c_void,
+) -> i32 {
+    let sink = thread::thread_arg::<AsyncSink<S, SLOTS, LINE_LEN>>(argp);
+
+    loop {
+        sink.drain();
+
+        // Wakeups sent while draining are counted, so none are missed.
+        sceKernelSleepThread();
+    }
+}
+
+impl<S: LogSink, const SLOTS: usize, const LINE_LEN: usize> LogSink
+    for AsyncSink<S, SLOTS, LINE_LEN>
+{
+    fn write(&self, stream: OutputStream, line: &str) {
+        while !self.queue.push(stream, line) {
+            match self.policy {
+                OverflowPolicy::DropNewest => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                OverflowPolicy::DropOldest => {
+                    if self.queue.pop(|_, _| {}) {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                        self.finished.fetch_add(1, Ordering::Release);
+                    }
+                }
+                OverflowPolicy::Block if self.writer_running() => unsafe {
+                    sceKernelDelayThread(BLOCK_DELAY_US);
+                },
+                OverflowPolicy::Block => {
+                    self.drain();
+                }
+            }
+        }
+
+        self.queued.fetch_add(1, Ordering::Release);
+
+        let thread = self.thread.load(Ordering::Acquire);
+        if thread != 0 {
+            unsafe {
+                sceKernelWakeupThread(SceUid(thread));
+            }
+        }
+    }
+
+    /// Write the frame to the inner sink on the calling thread, once every line queued before
+    /// it has been written.
+    ///
+    /// Frames don't fit the queue's lines, so unlike them, they aren't written in the background.
+    fn write_binary(&self, stream: OutputStream, frame: &[u8]) {
+        self.write_queued();
+
+        let _guard = self.draining.lock();
+        self.inner.write_binary(stream, frame);
+    }
+
+    fn open(&self) -> Result<(), i32> {
+        self.inner.open()
+    }
+
+    /// Wait for the writer thread to write every line queued so far, then flush the inner sink.
+    ///
+    /// If the writer thread hasn't been started, the queue is drained on the calling thread
+    /// instead.
+    fn flush(&self) {
+        self.write_queued();
+        self.inner.flush();
+    }
+
+    fn supports_color(&self) -> bool {
+        self.inner.supports_color()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RingBuffer;
+    use std::string::{String, ToString};
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Mutex;
+
+    fn lines<const SLOTS: usize>(sink: &AsyncSink<&RingBuffer<8>, SLOTS>) -> Vec<String> {
+        let mut lines = Vec::new();
+        sink.inner.for_each(|line| lines.push(line.to_string()));
+        lines
+    }
+
+    fn write_all<const SLOTS: usize>(sink: &AsyncSink<&RingBuffer<8>, SLOTS>, lines: &[&str]) {
+        for line in lines {
+            sink.write(OutputStream::StdErr, line);
+        }
+    }
+
+    #[test]
+    fn lines_are_only_written_when_drained() {
+        let ring = RingBuffer::new();
+        let sink: AsyncSink<_, 4> = AsyncSink::new(&ring, OverflowPolicy::DropNewest);
+
+        write_all(&sink, &["one\n", "two\n"]);
+        assert!(ring.is_empty());
+
+        assert_eq!(sink.drain(), 2);
+        assert_eq!(lines(&sink), ["one", "two"]);
+    }
+
+    #[test]
+    fn drop_newest_keeps_queued_lines() {
+        let ring = RingBuffer::new();
+        let sink: AsyncSink<_, 2> = AsyncSink::new(&ring, OverflowPolicy::DropNewest);
+
+        write_all(&sink, &["one\n", "two\n", "three\n", "four\n"]);
+        sink.flush();
+
+        assert_eq!(lines(&sink), ["one", "two"]);
+        assert_eq!(sink.dropped(), 2);
+    }
+
+    #[test]
+    fn drop_oldest_keeps_newest_lines() {
+        let ring = RingBuffer::new();
+        let sink: AsyncSink<_, 2> = AsyncSink::new(&ring, OverflowPolicy::DropOldest);
+
+        write_all(&sink, &["one\n", "two\n", "three\n", "four\n"]);
+        sink.flush();
+
+        assert_eq!(lines(&sink), ["three", "four"]);
+        assert_eq!(sink.dropped(), 2);
+    }
+
+    #[test]
+    fn block_without_writer_thread_drains_inline() {
+        let ring = RingBuffer::new();
+        let sink: AsyncSink<_, 2> = AsyncSink::new(&ring, OverflowPolicy::Block);
+
+        write_all(&sink, &["one\n", "two\n", "three\n"]);
+        assert_eq!(lines(&sink), ["one", "two"]);
+
+        sink.flush();
+        assert_eq!(lines(&sink), ["one", "two", "three"]);
+        assert_eq!(sink.dropped(), 0);
+    }
+
+    /// Sink recording every line and frame written to it.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl LogSink for Recorder {
+        fn write(&self, _stream: OutputStream, line: &str) {
+            self.0.lock().unwrap().push(line.to_string());
+        }
+
+        fn write_binary(&self, _stream: OutputStream, frame: &[u8]) {
+            self.0.lock().unwrap().push(format!("{:?}", frame));
+        }
+    }
+
+    #[test]
+    fn frames_are_written_after_queued_lines() {
+        let recorder = Recorder::default();
+        let sink: AsyncSink<_, 4> = AsyncSink::new(&recorder, OverflowPolicy::Block);
+
+        sink.write(OutputStream::StdErr, "one\n");
+        sink.write_binary(OutputStream::StdErr, &[0xff, 0]);
+        sink.write(OutputStream::StdErr, "two\n");
+        sink.flush();
+
+        assert_eq!(*recorder.0.lock().unwrap(), ["one\n", "[255, 0]", "two\n"]);
+    }
+
+    #[test]
+    fn flush_waits_for_the_writer_thread_to_write_in_order() {
+        let recorder = Recorder::default();
+        let sink: AsyncSink<_, 4> = AsyncSink::new(&recorder, OverflowPolicy::Block);
+        let stop = AtomicBool::new(false);
+
+        // PSP threads can't be created on the host, so stand in for the writer thread.
+        sink.thread.store(1, Ordering::Release);
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                while !stop.load(Ordering::Relaxed) {
+                    sink.drain();
+                    std::thread::yield_now();
+                }
+            });
+
+            for i in 0..200 {
+                sink.write(OutputStream::StdErr, &format!("{}\n", i));
+
+                if i % 7 == 0 {
+                    sink.flush();
+                    assert_eq!(recorder.0.lock().unwrap().len(), i + 1);
+                }
+            }
+
+            sink.flush();
+            stop.store(true, Ordering::Relaxed);
+        });
+
+        let expected: Vec<_> = (0..200).map(|i| format!("{}\n", i)).collect();
+        assert_eq!(*recorder.0.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn frames_wait_for_the_writer_thread_to_write_queued_lines() {
+        let recorder = Recorder::default();
+        let sink: AsyncSink<_, 4> = AsyncSink::new(&recorder, OverflowPolicy::Block);
+        let stop = AtomicBool::new(false);
+
+        // PSP threads can't be created on the host, so stand in for the writer thread.
+        sink.thread.store(1, Ordering::Release);
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                while !stop.load(Ordering::Relaxed) {
+                    sink.drain();
+                    std::thread::yield_now();
+                }
+            });
+
+            for i in 0..50u8 {
+                sink.write(OutputStream::StdErr, &format!("{}\n", i));
+                sink.write_binary(OutputStream::StdErr, &[i]);
+            }
+
+            sink.flush();
+            stop.store(true, Ordering::Relaxed);
+        });
+
+        let expected: Vec<_> = (0..50u8)
+            .flat_map(|i| [format!("{}\n", i), format!("{:?}", [i])])
+            .collect();
+        assert_eq!(*recorder.0.lock().unwrap(), expected);
+    }
+}
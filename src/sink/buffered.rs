@@ -0,0 +1,338 @@
+//! Sink batching lines into larger writes.
+
+use core::ffi::c_void;
+use core::sync::atomic::{AtomicI32, Ordering};
+
+use crate::lock::SleepLock;
+use crate::sys::*;
+use crate::thread;
+use crate::{LogSink, OutputStream};
+
+/// Sink that collects lines in a buffer of `N` bytes and writes them to another sink in
+/// batches.
+///
+/// This cuts down the number of writes, which matters most for the memory stick where small
+/// writes are very slow. The buffer is written out when:
+/// - it is full, or the next line is for a different [OutputStream];
+/// - the oldest buffered line is older than the delay set with
+///   [with_max_delay](Self::with_max_delay). A flush thread started with [start](Self::start)
+///   writes the buffer out as soon as it is due. Without one, this is only checked when a line
+///   is written, or when [flush_if_due](Self::flush_if_due) is called, e.g. once per frame;
+/// - a record at or above the logger's flush level is logged, see
+///   [PspLoggerConfig::with_flush_level](crate::PspLoggerConfig::with_flush_level);
+/// - the logger is flushed with `log::logger().flush()`.
+///
+/// Lines longer than the buffer are written straight through.
+///
+/// # Examples
+/// ```
+/// use psp_logger::{BufferedSink, FileSink, PspLoggerConfig};
+///
+/// static LOG_FILE: FileSink = FileSink::new("ms0:/PSP/GAME/MYAPP/app.log", 64 * 1024, 1);
+///
+/// // Write in 4KiB chunks, or at least once a second.
+/// static SINK: BufferedSink<&FileSink, 4096> =
+///     BufferedSink::new(&LOG_FILE).with_max_delay(1_000_000);
+///
+/// // Flush thread with a low priority and an 8KiB stack.
+/// let _ = SINK.start(0x70, 0x2000);
+///
+/// let config = PspLoggerConfig::new(log::LevelFilter::Info).with_sink(&SINK);
+/// ```
+pub struct BufferedSink<S: LogSink, const N: usize> {
+    inner: S,
+    max_delay_us: Option<u64>,
+    buffer: SleepLock<Buffer<N>>,
+    /// Id of the flush thread, or 0 if it hasn't been started.
+    thread: AtomicI32,
+}
+
+struct Buffer<const N: usize> {
+    data: [u8; N],
+    len: usize,
+    stream: OutputStream,
+    /// When the oldest buffered line was written, in microseconds since boot.
+    since: i64,
+}
+
+fn now() -> i64 {
+    unsafe { sceKernelGetSystemTimeWide() }
+}
+
+impl<S: LogSink, const N: usize> BufferedSink<S, N> {
+    /// Construct a buffered sink writing to `inner`.
+    pub const fn new(inner: S) -> Self {
+        BufferedSink {
+            inner,
+            max_delay_us: None,
+            buffer: SleepLock::with_value(Buffer {
+                data: [0; N],
+                len: 0,
+                stream: OutputStream::StdErr,
+                since: 0,
+            }),
+            thread: AtomicI32::new(0),
+        }
+    }
+
+    /// Write the buffer out once its oldest line has waited for `micros` microseconds.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    pub const fn with_max_delay(mut self, micros: u64) -> Self {
+        self.max_delay_us = Some(micros);
+        self
+    }
+
+    /// Start a thread writing the buffer out once its oldest line has waited for the maximum
+    /// delay.
+    ///
+    /// Does nothing if it is already running, or no maximum delay was set.
+    ///
+    /// # Arguments
+    /// - `priority`: Priority of the flush thread. Larger numbers are lower priorities.
+    /// - `stack_size`: Stack size of the flush thread in bytes. It needs enough room for the
+    ///   inner sink to write the buffer.
+    ///
+    /// Returns the PSP error code if the thread couldn't be created or started.
+    pub fn start(&'static self, priority: i32, stack_size: i32) -> Result<(), i32> {
+        if self.max_delay_us.is_none() || self.thread.load(Ordering::Acquire) != 0 {
+            return Ok(());
+        }
+
+        let thread = thread::spawn(
+            c"psp_logger_flush",
+            flush_thread::<S, N>,
+            priority,
+            stack_size,
+            self,
+        )?;
+        self.thread.store(thread.0, Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Write the buffer out if its oldest line has waited longer than the maximum delay.
+    pub fn flush_if_due(&self) {
+        let mut buffer = self.buffer.lock();
+
+        if self.is_due(&buffer) {
+            self.write_out(&mut buffer);
+        }
+    }
+
+    /// Microseconds until the buffer is due to be written out, or until a line written now would
+    /// be if it is empty.
+    fn time_until_due(&self, delay: u64) -> u32 {
+        let buffer = self.buffer.lock();
+        let waited = match buffer.len {
+            0 => 0,
+            _ => now().saturating_sub(buffer.since).max(0) as u64,
+        };
+
+        delay.saturating_sub(waited).min(u32::MAX as u64) as u32
+    }
+
+    fn is_due(&self, buffer: &Buffer<N>) -> bool {
+        match self.max_delay_us {
+            Some(delay) => buffer.len > 0 && now().saturating_sub(buffer.since) as u64 >= delay,
+            None => false,
+        }
+    }
+
+    fn write_out(&self, buffer: &mut Buffer<N>) {
+        if buffer.len == 0 {
+            return;
+        }
+
+        // Only ever filled with whole lines, so this is always valid UTF-8.
+        if let Ok(lines) = core::str::from_utf8(&buffer.data[..buffer.len]) {
+            self.inner.write(buffer.stream, lines);
+        }
+
+        buffer.len = 0;
+    }
+}
+
+unsafe extern "C" fn flush_thread<S: LogSink, const N: usize>(
+    _args: usize,
+    argp: *mut c_void,
+) -> i32 {
+    let sink = thread::thread_arg::<BufferedSink<S, N>>(argp);
+    // Only started with a maximum delay.
+    let delay = sink.max_delay_us.unwrap_or(0);
+
+    loop {
+        sceKernelDelayThread(sink.time_until_due(delay));
+        sink.flush_if_due();
+    }
+}
+
+impl<S: LogSink, const N: usize> LogSink for BufferedSink<S, N> {
+    fn write(&self, stream: OutputStream, line: &str) {
+        let mut buffer = self.buffer.lock();
+
+        if buffer.len > 0 && (buffer.stream != stream || buffer.len + line.len() > N) {
+            self.write_out(&mut buffer);
+        }
+
+        if line.len() > N {
+            self.inner.write(stream, line);
+            return;
+        }
+
+        if buffer.len == 0 {
+            buffer.stream = stream;
+            buffer.since = now();
+        }
+
+        let start = buffer.len;
+        buffer.data[start..start + line.len()].copy_from_slice(line.as_bytes());
+        buffer.len += line.len();
+
+        if buffer.len == N || self.is_due(&buffer) {
+            self.write_out(&mut buffer);
+        }
+    }
+
+    /// Write the buffered lines out, then the frame, so that they stay in order.
+    fn write_binary(&self, stream: OutputStream, frame: &[u8]) {
+        let mut buffer = self.buffer.lock();
+
+        self.write_out(&mut buffer);
+        self.inner.write_binary(stream, frame);
+    }
+
+    fn open(&self) -> Result<(), i32> {
+        self.inner.open()
+    }
+
+    fn flush(&self) {
+        self.write_out(&mut self.buffer.lock());
+        self.inner.flush();
+    }
+
+    fn supports_color(&self) -> bool {
+        self.inner.supports_color()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::{self, RecordedWrite, STDERR_FD, STDOUT_FD};
+    use crate::StdioSink;
+
+    fn write(fd: host::SceUid, data: &str) -> RecordedWrite {
+        RecordedWrite {
+            fd,
+            data: data.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn lines_are_batched_until_full() {
+        let sink: BufferedSink<_, 10> = BufferedSink::new(StdioSink);
+
+        let writes = host::capture(|| {
+            sink.write(OutputStream::StdErr, "one\n");
+            sink.write(OutputStream::StdErr, "two\n");
+            sink.write(OutputStream::StdErr, "three\n");
+            sink.write(OutputStream::StdErr, "four\n");
+        });
+
+        assert_eq!(
+            writes,
+            [write(STDERR_FD, "one\ntwo\n"), write(STDERR_FD, "three\n")]
+        );
+    }
+
+    #[test]
+    fn changing_stream_writes_buffer_out() {
+        let sink: BufferedSink<_, 64> = BufferedSink::new(StdioSink);
+
+        let writes = host::capture(|| {
+            sink.write(OutputStream::StdErr, "one\n");
+            sink.write(OutputStream::StdOut, "two\n");
+            sink.flush();
+        });
+
+        assert_eq!(
+            writes,
+            [write(STDERR_FD, "one\n"), write(STDOUT_FD, "two\n")]
+        );
+    }
+
+    #[test]
+    fn long_lines_are_written_straight_through() {
+        let sink: BufferedSink<_, 4> = BufferedSink::new(StdioSink);
+
+        let writes = host::capture(|| {
+            sink.write(OutputStream::StdErr, "a\n");
+            sink.write(OutputStream::StdErr, "too long\n");
+        });
+
+        assert_eq!(
+            writes,
+            [write(STDERR_FD, "a\n"), write(STDERR_FD, "too long\n")]
+        );
+    }
+
+    #[test]
+    fn buffer_is_written_out_after_max_delay() {
+        let sink: BufferedSink<_, 64> = BufferedSink::new(StdioSink).with_max_delay(1000);
+
+        let writes = host::capture(|| {
+            host::set_system_time(5000);
+            sink.write(OutputStream::StdErr, "one\n");
+            host::set_system_time(5999);
+            sink.flush_if_due();
+            sink.write(OutputStream::StdErr, "two\n");
+            host::set_system_time(6000);
+            sink.flush_if_due();
+        });
+
+        assert_eq!(writes, [write(STDERR_FD, "one\ntwo\n")]);
+    }
+
+    #[test]
+    fn frames_are_written_after_buffered_lines() {
+        let sink: BufferedSink<_, 64> = BufferedSink::new(StdioSink);
+
+        let writes = host::capture(|| {
+            sink.write(OutputStream::StdErr, "one\n");
+            sink.write(OutputStream::StdErr, "two\n");
+            sink.write_binary(OutputStream::StdErr, &[0xff, 0]);
+            sink.write(OutputStream::StdErr, "three\n");
+            sink.flush();
+        });
+
+        assert_eq!(
+            writes,
+            [
+                write(STDERR_FD, "one\ntwo\n"),
+                RecordedWrite {
+                    fd: STDERR_FD,
+                    data: vec![0xff, 0],
+                },
+                write(STDERR_FD, "three\n"),
+            ]
+        );
+    }
+
+    #[test]
+    fn flush_thread_sleeps_until_the_oldest_line_is_due() {
+        let sink: BufferedSink<_, 64> = BufferedSink::new(StdioSink).with_max_delay(1000);
+
+        host::capture(|| {
+            host::set_system_time(5000);
+            assert_eq!(sink.time_until_due(1000), 1000);
+
+            sink.write(OutputStream::StdErr, "one\n");
+            host::set_system_time(5400);
+            assert_eq!(sink.time_until_due(1000), 600);
+
+            host::set_system_time(7000);
+            assert_eq!(sink.time_until_due(1000), 0);
+        });
+    }
+}
@@ -0,0 +1,636 @@
+//! Sink writing to a file, rotating it once it reaches a given size.
+
+use core::fmt::Write;
+
+use crate::lock::SleepLock;
+use crate::sys::*;
+use crate::{LogSink, OutputStream};
+
+/// Longest path, in bytes, that [FileSink] can work with.
+const MAX_PATH: usize = 255;
+
+/// The file operations needed by [FileSink].
+///
+/// [PspFileSystem] implements these with the PSP's `sceIo*` syscalls. Other implementations
+/// allow the rotation logic to be exercised without a memory stick.
+///
+/// Errors are reported as the raw (negative) PSP error code.
+pub trait FileSystem: Sync {
+    /// Handle to an open file.
+    type File: Send;
+
+    /// Open a file for appending, creating it if it doesn't exist.
+    ///
+    /// Returns the handle along with the current size of the file in bytes.
+    fn open_append(&self, path: &str) -> Result<(Self::File, u64), i32>;
+
+    /// Append `data` to an open file, returning the number of bytes written.
+    fn write(&self, file: &Self::File, data: &[u8]) -> Result<usize, i32>;
+
+    /// Close a file.
+    fn close(&self, file: Self::File);
+
+    /// Rename a file, replacing `to` if it exists.
+    fn rename(&self, from: &str, to: &str) -> Result<(), i32>;
+
+    /// Delete a file.
+    fn remove(&self, path: &str) -> Result<(), i32>;
+
+    /// Create a directory. Its parent must already exist.
+    fn create_dir(&self, path: &str) -> Result<(), i32>;
+}
+
+/// [FileSystem] backed by the PSP's `sceIo*` syscalls.
+pub struct PspFileSystem;
+
+/// Sink that appends lines to a file, such as one on the memory stick.
+///
+/// Once writing a line would take the file past `max_size` bytes, it is rotated: `app.log` is
+/// renamed to `app.1.log`, `app.1.log` to `app.2.log` and so on, keeping at most `keep` old
+/// files. A fresh `app.log` is then started.
+///
+/// The file is opened on the first write, creating its parent directories if needed. If the file
+/// cannot be opened, lines are dropped and opening is retried on the next write.
+///
+/// # Examples
+/// ```
+/// use psp_logger::{FileSink, PspLoggerConfig};
+///
+/// // Rotate every 64KiB, keeping app.1.log to app.3.log around.
+/// static LOG_FILE: FileSink = FileSink::new("ms0:/PSP/GAME/MYAPP/logs/app.log", 64 * 1024, 3);
+///
+/// let config = PspLoggerConfig::new(log::LevelFilter::Info).with_sink(&LOG_FILE);
+/// ```
+pub struct FileSink<F: FileSystem = PspFileSystem> {
+    fs: F,
+    path: &'static str,
+    max_size: u64,
+    keep: usize,
+    /// Held across the file syscalls, which block.
+    file: SleepLock<Option<OpenFile<F::File>>>,
+}
+
+struct OpenFile<H> {
+    handle: H,
+    size: u64,
+}
+
+/// A NUL-terminated path held on the stack.
+struct PathBuf {
+    buf: [u8; MAX_PATH + 1],
+    len: usize,
+}
+
+impl PathBuf {
+    fn new() -> Self {
+        PathBuf {
+            buf: [0; MAX_PATH + 1],
+            len: 0,
+        }
+    }
+
+    fn from_str(path: &str) -> Option<Self> {
+        let mut buf = Self::new();
+        buf.write_str(path).ok()?;
+        Some(buf)
+    }
+
+    fn as_str(&self) -> &str {
+        // Only ever filled through `write_str`, so this is always valid UTF-8.
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+
+    fn as_ptr(&self) -> *const u8 {
+        self.buf.as_ptr()
+    }
+}
+
+impl Write for PathBuf {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let end = self.len + s.len();
+
+        if end > MAX_PATH {
+            return Err(core::fmt::Error);
+        }
+
+        self.buf[self.len..end].copy_from_slice(s.as_bytes());
+        self.buf[end] = 0;
+        self.len = end;
+
+        Ok(())
+    }
+}
+
+/// Build the name of the `index`th rotated file, e.g. `logs/app.log` -> `logs/app.1.log`.
+fn rotated_path(path: &str, index: usize) -> Option<PathBuf> {
+    let name_start = path.rfind(['/', ':']).map_or(0, |i| i + 1);
+    let mut buf = PathBuf::new();
+
+    let result = match path[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let (stem, ext) = path.split_at(name_start + dot);
+            write!(buf, "{}.{}{}", stem, index, ext)
+        }
+        _ => write!(buf, "{}.{}", path, index),
+    };
+
+    result.ok().map(|()| buf)
+}
+
+/// The directory containing `path`, if there is one.
+fn parent_dir(path: &str) -> Option<&str> {
+    let end = path.rfind('/')?;
+    let parent = &path[..end];
+
+    if parent.is_empty() || parent.ends_with(':') {
+        None
+    } else {
+        Some(parent)
+    }
+}
+
+impl FileSink<PspFileSystem> {
+    /// Construct a sink writing to a file on the PSP.
+    ///
+    /// # Arguments
+    /// - `path`: Path of the log file, e.g. `ms0:/PSP/GAME/MYAPP/logs/app.log`.
+    /// - `max_size`: Size in bytes at which the file is rotated.
+    /// - `keep`: Number of rotated files to keep. With 0, the file is simply truncated.
+    pub const fn new(path: &'static str, max_size: u64, keep: usize) -> Self {
+        Self::with_file_system(PspFileSystem, path, max_size, keep)
+    }
+}
+
+impl<F: FileSystem> FileSink<F> {
+    /// Construct a sink writing through the given [FileSystem].
+    ///
+    /// See [FileSink::new] for a description of the other arguments.
+    pub const fn with_file_system(fs: F, path: &'static str, max_size: u64, keep: usize) -> Self {
+        FileSink {
+            fs,
+            path,
+            max_size,
+            keep,
+            file: SleepLock::with_value(None),
+        }
+    }
+
+    fn open_file(&self) -> Result<OpenFile<F::File>, i32> {
+        let (handle, size) =
+            self.fs
+                .open_append(self.path)
+                .or_else(|err| match parent_dir(self.path) {
+                    Some(dir) => {
+                        let _ = self.create_dir_all(dir);
+                        self.fs.open_append(self.path)
+                    }
+                    None => Err(err),
+                })?;
+
+        Ok(OpenFile { handle, size })
+    }
+
+    /// Create `dir`, first creating any of its parents that are missing.
+    fn create_dir_all(&self, dir: &str) -> Result<(), i32> {
+        self.fs
+            .create_dir(dir)
+            .or_else(|err| match parent_dir(dir) {
+                Some(parent) => {
+                    self.create_dir_all(parent)?;
+                    self.fs.create_dir(dir)
+                }
+                None => Err(err),
+            })
+    }
+
+    fn rotate(&self, file: OpenFile<F::File>) -> Option<OpenFile<F::File>> {
+        self.fs.close(file.handle);
+
+        if self.keep == 0 {
+            let _ = self.fs.remove(self.path);
+        } else {
+            if let Some(oldest) = rotated_path(self.path, self.keep) {
+                let _ = self.fs.remove(oldest.as_str());
+            }
+
+            for index in (1..self.keep).rev() {
+                if let (Some(from), Some(to)) = (
+                    rotated_path(self.path, index),
+                    rotated_path(self.path, index + 1),
+                ) {
+                    let _ = self.fs.rename(from.as_str(), to.as_str());
+                }
+            }
+
+            if let Some(first) = rotated_path(self.path, 1) {
+                let _ = self.fs.rename(self.path, first.as_str());
+            }
+        }
+
+        self.open_file().ok()
+    }
+
+    /// Append `data` to the file, rotating it first if `data` would take it past `max_size`.
+    fn append(&self, data: &[u8]) {
+        let mut guard = self.file.lock();
+
+        let file = match guard.take().or_else(|| self.open_file().ok()) {
+            Some(file) if file.size > 0 && file.size + data.len() as u64 > self.max_size => {
+                self.rotate(file)
+            }
+            file => file,
+        };
+
+        *guard = file.map(|mut file| {
+            if let Ok(written) = self.fs.write(&file.handle, data) {
+                file.size += written as u64;
+            }
+
+            file
+        });
+    }
+}
+
+impl<F: FileSystem> LogSink for FileSink<F> {
+    fn write(&self, _stream: OutputStream, line: &str) {
+        self.append(line.as_bytes());
+    }
+
+    fn write_binary(&self, _stream: OutputStream, frame: &[u8]) {
+        self.append(frame);
+    }
+
+    /// Open the file now rather than on the first write, so that failures can be reported.
+    fn open(&self) -> Result<(), i32> {
+        let mut guard = self.file.lock();
+
+        if guard.is_none() {
+            *guard = Some(self.open_file()?);
+        }
+
+        Ok(())
+    }
+}
+
+impl FileSystem for PspFileSystem {
+    type File = SceUid;
+
+    fn open_append(&self, path: &str) -> Result<(SceUid, u64), i32> {
+        let path = PathBuf::from_str(path).ok_or(-1)?;
+        let flags = IoOpenFlags::WR_ONLY | IoOpenFlags::CREAT | IoOpenFlags::APPEND;
+
+        unsafe {
+            let fd = sceIoOpen(path.as_ptr(), flags, 0o777);
+
+            if fd.0 < 0 {
+                return Err(fd.0);
+            }
+
+            let size = sceIoLseek(fd, 0, IoWhence::End);
+            Ok((fd, size.max(0) as u64))
+        }
+    }
+
+    fn write(&self, file: &SceUid, data: &[u8]) -> Result<usize, i32> {
+        let written = unsafe { sceIoWrite(*file, data.as_ptr() as _, data.len()) };
+
+        if written < 0 {
+            Err(written)
+        } else {
+            Ok(written as usize)
+        }
+    }
+
+    fn close(&self, file: SceUid) {
+        unsafe {
+            sceIoClose(file);
+        }
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), i32> {
+        let from = PathBuf::from_str(from).ok_or(-1)?;
+        let to = PathBuf::from_str(to).ok_or(-1)?;
+
+        // sceIoRename fails if the destination exists, so make way for the file first.
+        unsafe {
+            sceIoRemove(to.as_ptr());
+        }
+
+        status(unsafe { sceIoRename(from.as_ptr(), to.as_ptr()) })
+    }
+
+    fn remove(&self, path: &str) -> Result<(), i32> {
+        let path = PathBuf::from_str(path).ok_or(-1)?;
+
+        status(unsafe { sceIoRemove(path.as_ptr()) })
+    }
+
+    fn create_dir(&self, path: &str) -> Result<(), i32> {
+        let path = PathBuf::from_str(path).ok_or(-1)?;
+
+        status(unsafe { sceIoMkdir(path.as_ptr(), 0o777) })
+    }
+}
+
+/// Create or truncate the file at `path`, opening it for writing.
+pub(crate) fn create_file(path: &str) -> Result<SceUid, i32> {
+    let path = PathBuf::from_str(path).ok_or(-1)?;
+
+    let fd = unsafe {
+        sceIoOpen(
+            path.as_ptr(),
+            IoOpenFlags::WR_ONLY | IoOpenFlags::CREAT | IoOpenFlags::TRUNC,
+            0o777,
+        )
+    };
+
+    if fd.0 < 0 {
+        Err(fd.0)
+    } else {
+        Ok(fd)
+    }
+}
+
+/// Read a whole file into `buf`, returning the number of bytes read.
+///
+/// Files that fill `buf` entirely are treated as too large, and rejected with `-1`.
+pub(crate) fn read_file(path: &str, buf: &mut [u8]) -> Result<usize, i32> {
+    let path = PathBuf::from_str(path).ok_or(-1)?;
+
+    unsafe {
+        let fd = sceIoOpen(path.as_ptr(), IoOpenFlags::RD_ONLY, 0);
+        if fd.0 < 0 {
+            return Err(fd.0);
+        }
+
+        let mut len = 0;
+        let result = loop {
+            let read = sceIoRead(fd, buf[len..].as_mut_ptr() as _, (buf.len() - len) as u32);
+
+            match read {
+                read if read < 0 => break Err(read),
+                0 => break Ok(len),
+                read => len += read as usize,
+            }
+
+            if len == buf.len() {
+                break Err(-1);
+            }
+        };
+
+        sceIoClose(fd);
+        result
+    }
+}
+
+fn status(code: i32) -> Result<(), i32> {
+    if code < 0 {
+        Err(code)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::string::{String, ToString};
+    use std::sync::Mutex;
+
+    /// In-memory file system. File handles are just the file's path.
+    #[derive(Default)]
+    struct MemoryFileSystem {
+        files: Mutex<BTreeMap<String, String>>,
+        dirs: Mutex<Vec<String>>,
+        read_only: bool,
+    }
+
+    /// Error returned by a read only [MemoryFileSystem].
+    const READ_ONLY: i32 = -2;
+
+    impl MemoryFileSystem {
+        fn contents(&self) -> Vec<(String, String)> {
+            self.files.lock().unwrap().clone().into_iter().collect()
+        }
+    }
+
+    impl FileSystem for MemoryFileSystem {
+        type File = String;
+
+        fn open_append(&self, path: &str) -> Result<(String, u64), i32> {
+            if self.read_only {
+                return Err(READ_ONLY);
+            }
+
+            let dir_exists = parent_dir(path)
+                .is_none_or(|dir| self.dirs.lock().unwrap().iter().any(|d| d == dir));
+
+            if !dir_exists {
+                return Err(-1);
+            }
+
+            let mut files = self.files.lock().unwrap();
+            let size = files.entry(path.to_string()).or_default().len();
+            Ok((path.to_string(), size as u64))
+        }
+
+        fn write(&self, file: &String, data: &[u8]) -> Result<usize, i32> {
+            let mut files = self.files.lock().unwrap();
+            files
+                .get_mut(file)
+                .unwrap()
+                .push_str(core::str::from_utf8(data).unwrap());
+            Ok(data.len())
+        }
+
+        fn close(&self, _file: String) {}
+
+        fn rename(&self, from: &str, to: &str) -> Result<(), i32> {
+            let mut files = self.files.lock().unwrap();
+            let contents = files.remove(from).ok_or(-1)?;
+            files.insert(to.to_string(), contents);
+            Ok(())
+        }
+
+        fn remove(&self, path: &str) -> Result<(), i32> {
+            self.files
+                .lock()
+                .unwrap()
+                .remove(path)
+                .map(|_| ())
+                .ok_or(-1)
+        }
+
+        fn create_dir(&self, path: &str) -> Result<(), i32> {
+            let mut dirs = self.dirs.lock().unwrap();
+            let parent_exists =
+                parent_dir(path).is_none_or(|parent| dirs.iter().any(|d| d == parent));
+
+            if !parent_exists || dirs.iter().any(|d| d == path) {
+                return Err(-1);
+            }
+
+            dirs.push(path.to_string());
+            Ok(())
+        }
+    }
+
+    fn sink(max_size: u64, keep: usize) -> FileSink<MemoryFileSystem> {
+        FileSink::with_file_system(
+            MemoryFileSystem::default(),
+            "ms0:/logs/app.log",
+            max_size,
+            keep,
+        )
+    }
+
+    fn files(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries
+            .iter()
+            .map(|(path, contents)| (path.to_string(), contents.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn rotated_path_inserts_index_before_extension() {
+        let rotated = |path, index| rotated_path(path, index).unwrap().as_str().to_string();
+
+        assert_eq!(rotated("ms0:/logs/app.log", 1), "ms0:/logs/app.1.log");
+        assert_eq!(rotated("ms0:/logs/app", 2), "ms0:/logs/app.2");
+        assert_eq!(rotated("ms0:/my.logs/app", 3), "ms0:/my.logs/app.3");
+        assert_eq!(rotated("ms0:/logs/.log", 1), "ms0:/logs/.log.1");
+    }
+
+    #[test]
+    fn creates_parent_directory_and_appends() {
+        let sink = sink(1024, 2);
+
+        sink.write(OutputStream::StdErr, "one\n");
+        sink.write(OutputStream::StdOut, "two\n");
+
+        assert_eq!(sink.fs.dirs.lock().unwrap().as_slice(), ["ms0:/logs"]);
+        assert_eq!(
+            sink.fs.contents(),
+            files(&[("ms0:/logs/app.log", "one\ntwo\n")])
+        );
+    }
+
+    #[test]
+    fn creates_missing_parent_directories() {
+        let sink = FileSink::with_file_system(
+            MemoryFileSystem::default(),
+            "ms0:/PSP/GAME/MYAPP/app.log",
+            1024,
+            1,
+        );
+        sink.fs.dirs.lock().unwrap().push("ms0:/PSP".to_string());
+
+        sink.write(OutputStream::StdErr, "one\n");
+
+        assert_eq!(
+            sink.fs.dirs.lock().unwrap().as_slice(),
+            ["ms0:/PSP", "ms0:/PSP/GAME", "ms0:/PSP/GAME/MYAPP"]
+        );
+        assert_eq!(
+            sink.fs.contents(),
+            files(&[("ms0:/PSP/GAME/MYAPP/app.log", "one\n")])
+        );
+    }
+
+    #[test]
+    fn rotates_when_full_and_keeps_newest_files() {
+        let sink = sink(8, 2);
+
+        for line in ["aaaa\n", "bbbb\n", "cccc\n", "dddd\n"] {
+            sink.write(OutputStream::StdErr, line);
+        }
+
+        assert_eq!(
+            sink.fs.contents(),
+            files(&[
+                ("ms0:/logs/app.1.log", "cccc\n"),
+                ("ms0:/logs/app.2.log", "bbbb\n"),
+                ("ms0:/logs/app.log", "dddd\n"),
+            ])
+        );
+    }
+
+    #[test]
+    fn existing_file_size_counts_towards_rotation() {
+        let sink = sink(8, 1);
+        sink.fs.create_dir("ms0:/logs").unwrap();
+        sink.fs
+            .files
+            .lock()
+            .unwrap()
+            .insert("ms0:/logs/app.log".to_string(), "old!!\n".to_string());
+
+        sink.write(OutputStream::StdErr, "new\n");
+
+        assert_eq!(
+            sink.fs.contents(),
+            files(&[
+                ("ms0:/logs/app.1.log", "old!!\n"),
+                ("ms0:/logs/app.log", "new\n")
+            ])
+        );
+    }
+
+    #[test]
+    fn keep_zero_truncates() {
+        let sink = sink(4, 0);
+
+        sink.write(OutputStream::StdErr, "one\n");
+        sink.write(OutputStream::StdErr, "two\n");
+
+        assert_eq!(sink.fs.contents(), files(&[("ms0:/logs/app.log", "two\n")]));
+    }
+
+    #[test]
+    fn lines_from_several_threads_are_kept_whole_across_rotations() {
+        let sink = sink(16, 100);
+
+        std::thread::scope(|scope| {
+            for thread in ["a", "b", "c", "d"] {
+                let sink = &sink;
+                scope.spawn(move || {
+                    for _ in 0..50 {
+                        sink.write(OutputStream::StdErr, &(thread.repeat(7) + "\n"));
+                    }
+                });
+            }
+        });
+
+        let contents = sink.fs.contents();
+        let written: usize = contents.iter().map(|(_, file)| file.len()).sum();
+        assert_eq!(written, 4 * 50 * 8);
+        assert!(contents.iter().all(|(_, file)| file.len() <= 16));
+        assert!(contents
+            .iter()
+            .flat_map(|(_, file)| file.lines())
+            .all(|line| line.len() == 7 && line.chars().all(|c| c == line.as_bytes()[0] as char)));
+    }
+
+    #[test]
+    fn open_creates_file_before_first_write() {
+        let sink = sink(100, 1);
+
+        sink.open().unwrap();
+        assert_eq!(sink.fs.contents(), files(&[("ms0:/logs/app.log", "")]));
+
+        sink.write(OutputStream::StdErr, "one\n");
+        assert_eq!(sink.fs.contents(), files(&[("ms0:/logs/app.log", "one\n")]));
+    }
+
+    #[test]
+    fn open_reports_failure() {
+        let fs = MemoryFileSystem {
+            read_only: true,
+            ..Default::default()
+        };
+        let sink = FileSink::with_file_system(fs, "ms0:/logs/app.log", 100, 1);
+
+        assert_eq!(sink.open(), Err(READ_ONLY));
+    }
+}
@@ -0,0 +1,240 @@
+//! Destinations that formatted log lines can be written to.
+
+mod async_sink;
+mod buffered;
+mod file;
+mod ring;
+
+use crate::sys::*;
+
+use crate::OutputStream;
+
+pub use async_sink::{AsyncSink, OverflowPolicy};
+pub use buffered::BufferedSink;
+pub(crate) use file::{create_file, read_file};
+pub use file::{FileSink, FileSystem, PspFileSystem};
+pub use ring::RingBuffer;
+
+/// A destination for formatted log lines.
+///
+/// [PspLogger](crate::PspLogger) formats each record into a single line and hands it to the
+/// sink configured with [PspLoggerConfig::with_sink](crate::PspLoggerConfig::with_sink),
+/// along with the [OutputStream] that the record's level is mapped to.
+///
+/// Sinks are shared between every thread that logs, so implementations must be [Sync].
+///
+/// # Examples
+/// ```
+/// use psp_logger::{LogSink, OutputStream, PspLoggerConfig};
+///
+/// struct Discard;
+///
+/// impl LogSink for Discard {
+///     fn write(&self, _stream: OutputStream, _line: &str) {}
+/// }
+///
+/// static DISCARD: Discard = Discard;
+///
+/// let config = PspLoggerConfig::new(log::LevelFilter::Info).with_sink(&DISCARD);
+/// ```
+pub trait LogSink: Sync {
+    /// Write a single log line.
+    ///
+    /// # Arguments
+    /// - `stream`: The stream that the record's level is mapped to.
+    /// - `line`: The formatted record, including the trailing newline.
+    fn write(&self, stream: OutputStream, line: &str);
+
+    /// Write a record logged with [log_binary](crate::log_binary), encoded as described in
+    /// [binary](crate::binary).
+    ///
+    /// Only sinks that keep raw bytes for a host to read back, such as stdio and files, need to
+    /// implement this, along with sinks wrapping another, which pass frames on in order with
+    /// their lines. Binary records are ignored by other sinks.
+    fn write_binary(&self, _stream: OutputStream, _frame: &[u8]) {}
+
+    /// Prepare the sink for writing, e.g. by opening a file.
+    ///
+    /// Called for each configured sink by [PspLogger::init](crate::PspLogger::init) and
+    /// [PspLogger::reconfigure](crate::PspLogger::reconfigure), which fail with the returned PSP
+    /// error code if this does. It may be called again on a sink that is already open. Sinks
+    /// that can't fail don't need to implement this.
+    fn open(&self) -> Result<(), i32> {
+        Ok(())
+    }
+
+    /// Flush any output buffered by the sink.
+    fn flush(&self) {}
+
+    /// Whether lines written to this sink may contain ANSI colour codes.
+    ///
+    /// Only sinks returning `true` receive coloured lines when
+    /// [PspLoggerConfig::with_colors](crate::PspLoggerConfig::with_colors) is used.
+    fn supports_color(&self) -> bool {
+        false
+    }
+}
+
+impl<T: LogSink + ?Sized> LogSink for &T {
+    fn write(&self, stream: OutputStream, line: &str) {
+        (**self).write(stream, line)
+    }
+
+    fn write_binary(&self, stream: OutputStream, frame: &[u8]) {
+        (**self).write_binary(stream, frame)
+    }
+
+    fn open(&self) -> Result<(), i32> {
+        (**self).open()
+    }
+
+    fn flush(&self) {
+        (**self).flush()
+    }
+
+    fn supports_color(&self) -> bool {
+        (**self).supports_color()
+    }
+}
+
+/// Sink writing every line to two other sinks.
+///
+/// Tees can be nested to write to more than two sinks.
+/// A tee only receives coloured lines if both of its sinks support them.
+///
+/// # Examples
+/// ```
+/// use psp_logger::{FileSink, PspLoggerConfig, StdioSink, Tee};
+///
+/// static LOG_FILE: FileSink = FileSink::new("ms0:/PSP/GAME/MYAPP/app.log", 64 * 1024, 1);
+/// static SINK: Tee<StdioSink, &FileSink> = Tee(StdioSink, &LOG_FILE);
+///
+/// let config = PspLoggerConfig::new(log::LevelFilter::Info).with_sink(&SINK);
+/// ```
+pub struct Tee<A, B>(pub A, pub B);
+
+impl<A: LogSink, B: LogSink> LogSink for Tee<A, B> {
+    fn write(&self, stream: OutputStream, line: &str) {
+        self.0.write(stream, line);
+        self.1.write(stream, line);
+    }
+
+    fn write_binary(&self, stream: OutputStream, frame: &[u8]) {
+        self.0.write_binary(stream, frame);
+        self.1.write_binary(stream, frame);
+    }
+
+    fn open(&self) -> Result<(), i32> {
+        self.0.open()?;
+        self.1.open()
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+        self.1.flush();
+    }
+
+    fn supports_color(&self) -> bool {
+        self.0.supports_color() && self.1.supports_color()
+    }
+}
+
+/// Maximum number of sinks in a single [Destinations] set.
+pub const MAX_DESTINATIONS: usize = 4;
+
+/// A set of sinks that a log level is written to.
+///
+/// # Examples
+/// ```
+/// use psp_logger::{Destinations, FileSink, PspLoggerConfig, RingBuffer, StdioSink};
+///
+/// static RECENT: RingBuffer<64> = RingBuffer::new();
+/// static LOG_FILE: FileSink = FileSink::new("ms0:/PSP/GAME/MYAPP/app.log", 64 * 1024, 1);
+///
+/// // Errors go to stderr, the ring buffer and the log file. Trace only goes to the ring buffer.
+/// let config = PspLoggerConfig::new(log::LevelFilter::Trace)
+///     .with_error_destinations(
+///         Destinations::new()
+///             .with(&StdioSink)
+///             .with(&RECENT)
+///             .with(&LOG_FILE),
+///     )
+///     .with_trace_destinations(Destinations::new().with(&RECENT));
+/// ```
+#[derive(Copy, Clone)]
+pub struct Destinations {
+    sinks: [Option<&'static dyn LogSink>; MAX_DESTINATIONS],
+}
+
+impl Destinations {
+    /// Construct an empty set. Levels mapped to an empty set are not written anywhere.
+    pub const fn new() -> Self {
+        Destinations {
+            sinks: [None; MAX_DESTINATIONS],
+        }
+    }
+
+    /// Add a sink to the set.
+    ///
+    /// Returns the struct to allow the method to be chained.
+    ///
+    /// # Panics
+    /// If the set already holds [MAX_DESTINATIONS] sinks.
+    pub const fn with(mut self, sink: &'static dyn LogSink) -> Self {
+        let mut i = 0;
+
+        while i < MAX_DESTINATIONS {
+            if self.sinks[i].is_none() {
+                self.sinks[i] = Some(sink);
+                return self;
+            }
+
+            i += 1;
+        }
+
+        panic!("too many destinations");
+    }
+
+    /// Iterate over the sinks in the set.
+    pub fn iter(&self) -> impl Iterator<Item = &'static dyn LogSink> + '_ {
+        self.sinks.iter().flatten().copied()
+    }
+}
+
+impl Default for Destinations {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The default sink, writing lines to the PSP's stdout or stderr.
+///
+/// Lines are written as they are. Unlike version 0.1, no NUL byte follows each line.
+pub struct StdioSink;
+
+impl StdioSink {
+    fn write_bytes(&self, stream: OutputStream, data: &[u8]) {
+        unsafe {
+            let fh = match stream {
+                OutputStream::StdErr => sceKernelStderr(),
+                OutputStream::StdOut => sceKernelStdout(),
+            };
+
+            sceIoWrite(fh, data.as_ptr() as _, data.len());
+        }
+    }
+}
+
+impl LogSink for StdioSink {
+    fn write(&self, stream: OutputStream, line: &str) {
+        self.write_bytes(stream, line.as_bytes());
+    }
+
+    fn write_binary(&self, stream: OutputStream, frame: &[u8]) {
+        self.write_bytes(stream, frame);
+    }
+
+    fn supports_color(&self) -> bool {
+        true
+    }
+}
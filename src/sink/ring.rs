@@ -0,0 +1,384 @@
+//! Sink keeping the most recent lines in memory.
+
+use crate::dump::LineVisitor;
+use crate::lock::SleepLock;
+use crate::{LogSink, OutputStream};
+
+/// Sink that keeps the last `LINES` log lines in RAM.
+///
+/// Each line is stored in a fixed slot of `LINE_LEN` bytes, so the buffer never allocates and
+/// writing to it never touches the file system. Lines longer than a slot are truncated. Once
+/// full, each new line replaces the oldest one.
+///
+/// Lines are stored and handed back without their trailing newline.
+///
+/// To keep writing to stdout/stderr as well, combine it with [StdioSink](crate::StdioSink)
+/// using a [Tee](crate::Tee).
+///
+/// # Examples
+/// ```
+/// use psp_logger::{PspLoggerConfig, RingBuffer, StdioSink, Tee};
+///
+/// static RECENT: RingBuffer<64> = RingBuffer::new();
+/// static SINK: Tee<StdioSink, &RingBuffer<64>> = Tee(StdioSink, &RECENT);
+///
+/// let config = PspLoggerConfig::new(log::LevelFilter::Trace).with_sink(&SINK);
+///
+/// // Later, e.g. when showing an on-screen log viewer.
+/// RECENT.for_each(|line| {
+///     // Draw the line.
+/// });
+/// ```
+pub struct RingBuffer<const LINES: usize, const LINE_LEN: usize = 128> {
+    ring: SleepLock<Ring<LINES, LINE_LEN>>,
+}
+
+struct Ring<const LINES: usize, const LINE_LEN: usize> {
+    slots: [[u8; LINE_LEN]; LINES],
+    lens: [usize; LINES],
+    /// Slot holding the oldest line.
+    head: usize,
+    count: usize,
+    /// Number of lines ever written, wrapping. The lines held are numbered from
+    /// `written - count` up to `written`.
+    written: usize,
+}
+
+/// Whether line number `a` comes before line number `b`, allowing for wrapping.
+fn before(a: usize, b: usize) -> bool {
+    (a.wrapping_sub(b) as isize) < 0
+}
+
+/// A line copied out of a slot.
+fn line_str(line: &[u8]) -> &str {
+    // Slots are only filled from a `&str` truncated at a character boundary.
+    core::str::from_utf8(line).unwrap_or("")
+}
+
+/// Truncate `s` to at most `len` bytes without splitting a character.
+fn truncate(s: &str, len: usize) -> &str {
+    if s.len() <= len {
+        return s;
+    }
+
+    let mut end = len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    &s[..end]
+}
+
+impl<const LINES: usize, const LINE_LEN: usize> Ring<LINES, LINE_LEN> {
+    fn push(&mut self, line: &str) {
+        if LINES == 0 {
+            return;
+        }
+
+        let line = truncate(line, LINE_LEN);
+        let slot = (self.head + self.count) % LINES;
+
+        self.slots[slot][..line.len()].copy_from_slice(line.as_bytes());
+        self.lens[slot] = line.len();
+
+        if self.count == LINES {
+            self.head = (self.head + 1) % LINES;
+        } else {
+            self.count += 1;
+        }
+        self.written = self.written.wrapping_add(1);
+    }
+
+    /// Number of the oldest line held.
+    fn oldest(&self) -> usize {
+        self.written.wrapping_sub(self.count)
+    }
+
+    /// Copy the `index`th line, counting from the oldest, into `buf`, returning its length.
+    fn copy_out(&self, index: usize, buf: &mut [u8; LINE_LEN]) -> usize {
+        let line = self.get(index).as_bytes();
+        buf[..line.len()].copy_from_slice(line);
+        line.len()
+    }
+
+    /// The `index`th line, counting from the oldest.
+    fn get(&self, index: usize) -> &str {
+        let slot = (self.head + index) % LINES;
+
+        // Slots are only filled from a `&str` truncated at a character boundary.
+        core::str::from_utf8(&self.slots[slot][..self.lens[slot]]).unwrap_or("")
+    }
+}
+
+impl<const LINES: usize, const LINE_LEN: usize> RingBuffer<LINES, LINE_LEN> {
+    /// Construct an empty ring buffer.
+    pub const fn new() -> Self {
+        RingBuffer {
+            ring: SleepLock::with_value(Ring {
+                slots: [[0; LINE_LEN]; LINES],
+                lens: [0; LINES],
+                head: 0,
+                count: 0,
+                written: 0,
+            }),
+        }
+    }
+
+    /// Maximum number of lines held by the buffer.
+    pub const fn capacity(&self) -> usize {
+        LINES
+    }
+
+    /// Number of lines currently held.
+    pub fn len(&self) -> usize {
+        self.ring.lock().count
+    }
+
+    /// Whether the buffer holds no lines.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all lines.
+    pub fn clear(&self) {
+        let mut ring = self.ring.lock();
+        ring.head = 0;
+        ring.count = 0;
+    }
+
+    /// Call `f` with each line, oldest first.
+    ///
+    /// The buffer is only locked while each line is copied out, so `f` may log. Lines written
+    /// after this is called aren't visited, nor are lines replaced before `f` gets to them.
+    pub fn for_each<F: FnMut(&str)>(&self, mut f: F) {
+        let (mut next, end) = {
+            let ring = self.ring.lock();
+            (ring.oldest(), ring.written)
+        };
+        let mut buf = [0; LINE_LEN];
+
+        loop {
+            let len = {
+                let ring = self.ring.lock();
+                if before(next, ring.oldest()) {
+                    next = ring.oldest();
+                }
+                if !before(next, end) {
+                    break;
+                }
+
+                ring.copy_out(next.wrapping_sub(ring.oldest()), &mut buf)
+            };
+
+            f(line_str(&buf[..len]));
+            next = next.wrapping_add(1);
+        }
+    }
+
+    /// Call `f` with each line, oldest first, unless the buffer is locked by another thread.
+    ///
+    /// This is for contexts that must never wait, such as an exception handler. Returns whether
+    /// the lines were visited.
+    pub fn try_for_each<F: FnMut(&str)>(&self, mut f: F) -> bool {
+        let Some(ring) = self.ring.lock_with_retries(1) else {
+            return false;
+        };
+
+        for index in 0..ring.count {
+            f(ring.get(index));
+        }
+
+        true
+    }
+
+    /// Lock the buffer without waiting, and call `f` with a function visiting each line.
+    pub(crate) fn try_locked<R>(&self, f: impl FnOnce(&LineVisitor) -> R) -> Option<R> {
+        let ring = self.ring.lock_with_retries(1)?;
+
+        Some(f(&|visit: &mut dyn FnMut(&str)| {
+            for index in 0..ring.count {
+                visit(ring.get(index));
+            }
+        }))
+    }
+
+    /// Remove each line, oldest first, and call `f` with it.
+    ///
+    /// The buffer is only locked while each line is taken out, so `f` may log. At most as many
+    /// lines as the buffer held when this was called are removed.
+    pub fn drain<F: FnMut(&str)>(&self, mut f: F) {
+        let mut remaining = self.len();
+        let mut buf = [0; LINE_LEN];
+
+        while remaining > 0 {
+            let len = {
+                let mut ring = self.ring.lock();
+                if ring.count == 0 {
+                    break;
+                }
+
+                let len = ring.copy_out(0, &mut buf);
+                ring.head = (ring.head + 1) % LINES;
+                ring.count -= 1;
+                len
+            };
+
+            f(line_str(&buf[..len]));
+            remaining -= 1;
+        }
+    }
+
+    /// Copy every line out of the buffer, oldest first.
+    #[cfg(feature = "alloc")]
+    pub fn lines(&self) -> alloc::vec::Vec<alloc::string::String> {
+        let mut lines = alloc::vec::Vec::with_capacity(self.len());
+        self.for_each(|line| lines.push(line.into()));
+        lines
+    }
+
+    /// Copy the most recent lines into `buf`, oldest first and each followed by a newline.
+    ///
+    /// Only whole lines are copied, so if `buf` is too small the oldest lines are left out.
+    ///
+    /// Returns the number of bytes written to `buf`.
+    pub fn copy_to(&self, buf: &mut [u8]) -> usize {
+        let ring = self.ring.lock();
+
+        let mut first = ring.count;
+        let mut total = 0;
+        while first > 0 && total + ring.get(first - 1).len() < buf.len() {
+            first -= 1;
+            total += ring.get(first).len() + 1;
+        }
+
+        let mut pos = 0;
+        for index in first..ring.count {
+            let line = ring.get(index).as_bytes();
+            buf[pos..pos + line.len()].copy_from_slice(line);
+            buf[pos + line.len()] = b'\n';
+            pos += line.len() + 1;
+        }
+
+        pos
+    }
+}
+
+impl<const LINES: usize, const LINE_LEN: usize> Default for RingBuffer<LINES, LINE_LEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const LINES: usize, const LINE_LEN: usize> LogSink for RingBuffer<LINES, LINE_LEN> {
+    fn write(&self, _stream: OutputStream, line: &str) {
+        self.ring
+            .lock()
+            .push(line.strip_suffix('\n').unwrap_or(line));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::{String, ToString};
+
+    fn lines<const LINES: usize, const LINE_LEN: usize>(
+        ring: &RingBuffer<LINES, LINE_LEN>,
+    ) -> Vec<String> {
+        let mut lines = Vec::new();
+        ring.for_each(|line| lines.push(line.to_string()));
+        lines
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn lines_copies_out_oldest_first() {
+        let ring: RingBuffer<2> = RingBuffer::new();
+        for line in ["one\n", "two\n", "three\n"] {
+            ring.write(OutputStream::StdErr, line);
+        }
+
+        assert_eq!(ring.lines(), ["two", "three"]);
+    }
+
+    #[test]
+    fn keeps_most_recent_lines() {
+        let ring: RingBuffer<3> = RingBuffer::new();
+
+        for line in ["one\n", "two\n", "three\n", "four\n"] {
+            ring.write(OutputStream::StdErr, line);
+        }
+
+        assert_eq!(ring.len(), 3);
+        assert_eq!(lines(&ring), ["two", "three", "four"]);
+    }
+
+    #[test]
+    fn long_lines_are_truncated_on_char_boundary() {
+        let ring: RingBuffer<2, 4> = RingBuffer::new();
+
+        ring.write(OutputStream::StdErr, "abcdef\n");
+        ring.write(OutputStream::StdErr, "ab\u{e9}\u{e9}\n");
+
+        assert_eq!(lines(&ring), ["abcd", "ab\u{e9}"]);
+    }
+
+    #[test]
+    fn drain_empties_buffer() {
+        let ring: RingBuffer<4> = RingBuffer::new();
+        ring.write(OutputStream::StdErr, "one\n");
+        ring.write(OutputStream::StdOut, "two\n");
+
+        let mut drained = Vec::new();
+        ring.drain(|line| drained.push(line.to_string()));
+
+        assert_eq!(drained, ["one", "two"]);
+        assert!(ring.is_empty());
+
+        ring.write(OutputStream::StdErr, "three\n");
+        assert_eq!(lines(&ring), ["three"]);
+    }
+
+    /// Ring buffer that logs a line into itself for each line visited.
+    static NOISY: RingBuffer<3> = RingBuffer::new();
+
+    #[test]
+    fn lines_can_be_written_while_visiting() {
+        for line in ["one\n", "two\n"] {
+            NOISY.write(OutputStream::StdErr, line);
+        }
+
+        let mut visited = Vec::new();
+        NOISY.for_each(|line| {
+            visited.push(line.to_string());
+            NOISY.write(OutputStream::StdErr, "visited\n");
+        });
+        // The second visit replaced "one", so "two" is the oldest line left to visit.
+        assert_eq!(visited, ["one", "two"]);
+        assert_eq!(lines(&NOISY), ["two", "visited", "visited"]);
+
+        let mut drained = Vec::new();
+        NOISY.drain(|line| {
+            drained.push(line.to_string());
+            NOISY.write(OutputStream::StdErr, "drained\n");
+        });
+        assert_eq!(drained, ["two", "visited", "visited"]);
+        assert_eq!(lines(&NOISY), ["drained", "drained", "drained"]);
+    }
+
+    #[test]
+    fn copy_to_keeps_newest_whole_lines() {
+        let ring: RingBuffer<4> = RingBuffer::new();
+        for line in ["one\n", "two\n", "three\n"] {
+            ring.write(OutputStream::StdErr, line);
+        }
+
+        let mut buf = [0; 32];
+        let len = ring.copy_to(&mut buf);
+        assert_eq!(&buf[..len], b"one\ntwo\nthree\n");
+
+        let mut buf = [0; 12];
+        let len = ring.copy_to(&mut buf);
+        assert_eq!(&buf[..len], b"two\nthree\n");
+    }
+}
@@ -0,0 +1,41 @@
+//! The PSP syscalls used by the logger.
+//!
+//! On the PSP these come straight from `psp::sys`. On any other target they are provided by
+//! the [host](crate::host) backend instead, so the crate can be built and tested on a
+//! development machine.
+
+#[cfg(target_os = "psp")]
+pub(crate) use psp::sys::{
+    sceIoClose, sceIoLseek, sceIoMkdir, sceIoOpen, sceIoRead, sceIoRemove, sceIoRename, sceIoWrite,
+    sceKernelCreateThread, sceKernelDelayThread, sceKernelDeleteThread, sceKernelDevkitVersion,
+    sceKernelGetModuleIdList, sceKernelGetSystemTimeWide, sceKernelGetThreadId,
+    sceKernelIsCpuIntrEnable, sceKernelQueryModuleInfo, sceKernelReferThreadStatus,
+    sceKernelSleepThread, sceKernelStartThread, sceKernelStderr, sceKernelStdout,
+    sceKernelWakeupThread, sceRtcGetCurrentClockLocalTime, IoOpenFlags, IoWhence,
+    SceKernelModuleInfo, SceKernelThreadEntry, SceKernelThreadInfo, ScePspDateTime, SceUid,
+    ThreadAttributes,
+};
+
+#[cfg(not(target_os = "psp"))]
+pub(crate) use crate::host::{
+    sceIoClose, sceIoLseek, sceIoMkdir, sceIoOpen, sceIoRead, sceIoRemove, sceIoRename, sceIoWrite,
+    sceKernelCreateThread, sceKernelDelayThread, sceKernelDeleteThread, sceKernelDevkitVersion,
+    sceKernelGetModuleIdList, sceKernelGetSystemTimeWide, sceKernelGetThreadId,
+    sceKernelIsCpuIntrEnable, sceKernelQueryModuleInfo, sceKernelReferThreadStatus,
+    sceKernelSleepThread, sceKernelStartThread, sceKernelStderr, sceKernelStdout,
+    sceKernelWakeupThread, sceRtcGetCurrentClockLocalTime, IoOpenFlags, IoWhence,
+    SceKernelModuleInfo, SceKernelThreadEntry, SceKernelThreadInfo, ScePspDateTime, SceUid,
+    ThreadAttributes,
+};
+
+/// An address in the code of the module the logger is linked into.
+#[cfg(target_os = "psp")]
+pub(crate) fn code_address() -> u32 {
+    code_address as usize as u32
+}
+
+#[cfg(not(target_os = "psp"))]
+pub(crate) use crate::host::code_address;
+
+/// Error code returned by `sceIoOpen` when the file doesn't exist.
+pub(crate) const SCE_ERROR_ERRNO_ENOENT: i32 = 0x8001_0002_u32 as i32;
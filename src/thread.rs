@@ -0,0 +1,299 @@
+//! Identification of the thread that logged a record.
+
+use core::ffi::{c_void, CStr};
+use core::fmt::{self, Display};
+use core::mem::MaybeUninit;
+use core::ops::Range;
+use core::ptr::{addr_of, addr_of_mut};
+
+use crate::lock::SleepLock;
+use crate::sys::*;
+
+/// Length of the name buffer in `SceKernelThreadInfo`.
+const NAME_LEN: usize = 32;
+
+/// Number of thread names remembered by the cache.
+const CACHE_SIZE: usize = 16;
+
+/// Which details of the current thread to prefix each line with.
+///
+/// See [PspLoggerConfig::with_thread_info](crate::PspLoggerConfig::with_thread_info).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ThreadInfo {
+    /// Don't prefix lines with any thread details.
+    None,
+
+    /// Prefix lines with the thread's id, e.g. `[0x04a1b2c3] `.
+    Id,
+
+    /// Prefix lines with the thread's id and name, e.g. `[0x04a1b2c3 render] `.
+    ///
+    /// Names are remembered by id, so a thread given the id of one that has been deleted may be
+    /// shown with the old thread's name.
+    IdAndName,
+}
+
+/// Create and start a thread running `entry`, passing it `arg`.
+///
+/// `entry` gets `arg` back with [thread_arg]. Returns the new thread's id, or the PSP error code
+/// if it couldn't be created or started.
+pub(crate) fn spawn<T>(
+    name: &CStr,
+    entry: SceKernelThreadEntry,
+    priority: i32,
+    stack_size: i32,
+    arg: &'static T,
+) -> Result<SceUid, i32> {
+    unsafe {
+        let thread = sceKernelCreateThread(
+            name.as_ptr() as *const u8,
+            entry,
+            priority,
+            stack_size,
+            ThreadAttributes::USER,
+            core::ptr::null_mut(),
+        );
+
+        if thread.0 < 0 {
+            return Err(thread.0);
+        }
+
+        // The kernel copies the argument onto the new thread's stack.
+        let mut arg = arg as *const T;
+        let result = sceKernelStartThread(
+            thread,
+            core::mem::size_of::<*const T>(),
+            &mut arg as *mut *const T as *mut c_void,
+        );
+
+        if result < 0 {
+            sceKernelDeleteThread(thread);
+            return Err(result);
+        }
+
+        Ok(thread)
+    }
+}
+
+/// The argument given to [spawn], from the `argp` the thread's entry function was called with.
+///
+/// # Safety
+/// `argp` must be the one passed to a thread started by [spawn] with an argument of type `T`.
+pub(crate) unsafe fn thread_arg<'a, T>(argp: *mut c_void) -> &'a T {
+    &*(*(argp as *const *const T))
+}
+
+/// The id of the current thread.
+pub(crate) fn current_id() -> i32 {
+    unsafe { sceKernelGetThreadId() }
+}
+
+/// Call `f` with the name of the current thread.
+///
+/// Names are cached per thread id, so the kernel is only queried the first time a thread logs.
+/// See [NameCache] for what that means for reused ids.
+pub(crate) fn with_current_name<R>(f: impl FnOnce(&str) -> R) -> R {
+    let id = current_id();
+
+    // Copied out so that the cache isn't locked while `f` runs.
+    let mut name = [0; NAME_LEN];
+    let len = {
+        let mut cache = NAME_CACHE.lock();
+        let cached = cache.get_or_insert_with(id, |buf| query_name(id, buf));
+        name[..cached.len()].copy_from_slice(cached.as_bytes());
+        cached.len()
+    };
+
+    f(core::str::from_utf8(&name[..len]).unwrap_or("?"))
+}
+
+/// Ask the kernel for the name of a thread.
+fn query_name(id: i32, buf: &mut [u8; NAME_LEN]) -> bool {
+    let mut info = MaybeUninit::<SceKernelThreadInfo>::zeroed();
+    let info = info.as_mut_ptr();
+
+    unsafe {
+        addr_of_mut!((*info).size).write(core::mem::size_of::<SceKernelThreadInfo>());
+
+        if sceKernelReferThreadStatus(SceUid(id), info) < 0 {
+            return false;
+        }
+
+        *buf = addr_of!((*info).name).read();
+    }
+
+    true
+}
+
+/// The addresses of the current thread's stack, or `None` if the kernel can't be asked.
+pub(crate) fn current_stack() -> Option<Range<u32>> {
+    let mut info = MaybeUninit::<SceKernelThreadInfo>::zeroed();
+    let info = info.as_mut_ptr();
+
+    unsafe {
+        addr_of_mut!((*info).size).write(core::mem::size_of::<SceKernelThreadInfo>());
+
+        if sceKernelReferThreadStatus(SceUid(current_id()), info) < 0 {
+            return None;
+        }
+
+        let base = addr_of!((*info).stack).read() as u32;
+        let size = addr_of!((*info).stack_size).read() as u32;
+
+        Some(base..base.checked_add(size)?)
+    }
+}
+
+static NAME_CACHE: SleepLock<NameCache<CACHE_SIZE>> = SleepLock::with_value(NameCache::new());
+
+#[derive(Copy, Clone)]
+struct CachedName {
+    id: i32,
+    name: [u8; NAME_LEN],
+}
+
+/// Fixed-size cache of thread names. Once full, the oldest entry is replaced.
+///
+/// Entries aren't removed when their thread ends. The kernel can hand a deleted thread's id to a
+/// new thread, which is then shown with the old name until the entry is replaced. Checking the
+/// name again on every hit would cost the syscall the cache is there to save.
+struct NameCache<const N: usize> {
+    entries: [Option<CachedName>; N],
+    next: usize,
+}
+
+impl<const N: usize> NameCache<N> {
+    const fn new() -> Self {
+        NameCache {
+            entries: [None; N],
+            next: 0,
+        }
+    }
+
+    /// Look up the name of thread `id`, calling `query` to fill it in if it isn't cached.
+    ///
+    /// Names that can't be queried are shown as `?` and not cached.
+    fn get_or_insert_with(
+        &mut self,
+        id: i32,
+        query: impl FnOnce(&mut [u8; NAME_LEN]) -> bool,
+    ) -> &str {
+        let index = match self
+            .entries
+            .iter()
+            .position(|entry| matches!(entry, Some(entry) if entry.id == id))
+        {
+            Some(index) => index,
+            None => {
+                let mut name = [0; NAME_LEN];
+
+                if N == 0 || !query(&mut name) {
+                    return "?";
+                }
+
+                let index = self.next;
+                self.entries[index] = Some(CachedName { id, name });
+                self.next = (self.next + 1) % N;
+                index
+            }
+        };
+
+        match &self.entries[index] {
+            Some(entry) => {
+                let len = entry.name.iter().position(|&b| b == 0).unwrap_or(NAME_LEN);
+                core::str::from_utf8(&entry.name[..len]).unwrap_or("?")
+            }
+            None => "?",
+        }
+    }
+}
+
+/// Displays the thread prefix configured by a [ThreadInfo].
+pub(crate) struct ThreadPrefix(pub ThreadInfo);
+
+impl Display for ThreadPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            ThreadInfo::None => Ok(()),
+            ThreadInfo::Id => write!(f, "[{:#010x}] ", current_id()),
+            ThreadInfo::IdAndName => {
+                with_current_name(|name| write!(f, "[{:#010x} {}] ", current_id(), name))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host;
+    use std::string::ToString;
+
+    fn fill(name: &str) -> impl FnOnce(&mut [u8; NAME_LEN]) -> bool + '_ {
+        move |buf| {
+            buf[..name.len()].copy_from_slice(name.as_bytes());
+            true
+        }
+    }
+
+    #[test]
+    fn names_are_queried_once_per_thread() {
+        let mut cache: NameCache<4> = NameCache::new();
+
+        assert_eq!(cache.get_or_insert_with(1, fill("render")), "render");
+        assert_eq!(cache.get_or_insert_with(2, fill("audio")), "audio");
+        assert_eq!(cache.get_or_insert_with(1, |_| unreachable!()), "render");
+    }
+
+    #[test]
+    fn oldest_name_is_evicted_when_full() {
+        let mut cache: NameCache<2> = NameCache::new();
+
+        cache.get_or_insert_with(1, fill("one"));
+        cache.get_or_insert_with(2, fill("two"));
+        cache.get_or_insert_with(3, fill("three"));
+
+        assert_eq!(cache.get_or_insert_with(1, fill("uno")), "uno");
+        assert_eq!(cache.get_or_insert_with(3, |_| unreachable!()), "three");
+    }
+
+    #[test]
+    fn names_are_found_after_empty_entries() {
+        let mut cache: NameCache<4> = NameCache::new();
+        cache.next = 2;
+
+        cache.get_or_insert_with(1, fill("render"));
+        assert_eq!(cache.get_or_insert_with(1, |_| unreachable!()), "render");
+    }
+
+    #[test]
+    fn failed_queries_are_not_cached() {
+        let mut cache: NameCache<2> = NameCache::new();
+
+        assert_eq!(cache.get_or_insert_with(1, |_| false), "?");
+        assert_eq!(cache.get_or_insert_with(1, fill("late")), "late");
+    }
+
+    #[test]
+    fn names_can_be_looked_up_while_using_one() {
+        host::with_thread(0x04a1b2c3, "render", || {
+            let names = with_current_name(|outer| {
+                with_current_name(|inner| std::format!("{} {}", outer, inner))
+            });
+
+            assert_eq!(names, "render render");
+        });
+    }
+
+    #[test]
+    fn prefix_shows_id_and_name() {
+        host::with_thread(0x04a1b2c3, "render", || {
+            assert_eq!(ThreadPrefix(ThreadInfo::None).to_string(), "");
+            assert_eq!(ThreadPrefix(ThreadInfo::Id).to_string(), "[0x04a1b2c3] ");
+            assert_eq!(
+                ThreadPrefix(ThreadInfo::IdAndName).to_string(),
+                "[0x04a1b2c3 render] "
+            );
+        });
+    }
+}
@@ -0,0 +1,152 @@
+//! Sources of the timestamps shown by the `{time}` format placeholder.
+
+use core::fmt::{self, Display};
+
+use crate::sys::*;
+
+/// A point in time, as reported by a [TimeSource].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Timestamp {
+    /// Microseconds since the PSP booted.
+    ///
+    /// Displayed as seconds with microsecond precision, e.g. `12.000345`.
+    SinceBoot(u64),
+
+    /// Local wall-clock date and time.
+    ///
+    /// Displayed as `YYYY-MM-DD hh:mm:ss.uuuuuu`.
+    Local(DateTime),
+}
+
+/// A calendar date and time of day.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u16,
+    pub day: u16,
+    pub hour: u16,
+    pub minute: u16,
+    pub second: u16,
+    pub microsecond: u32,
+}
+
+/// Something that can tell the time.
+///
+/// Set with [PspLoggerConfig::with_time_source](crate::PspLoggerConfig::with_time_source).
+/// Custom implementations can be used to make timestamps deterministic in tests.
+pub trait TimeSource: Sync {
+    /// The current time.
+    fn now(&self) -> Timestamp;
+}
+
+/// Monotonic time since boot, from `sceKernelGetSystemTimeWide`.
+///
+/// This is the default time source, and is best suited to measuring the time between records.
+pub struct SystemClock;
+
+/// Local wall-clock time, from `sceRtcGetCurrentClockLocalTime`.
+///
+/// Useful for logs collected from testers, where the real date and time matter.
+pub struct RtcClock;
+
+impl TimeSource for SystemClock {
+    fn now(&self) -> Timestamp {
+        Timestamp::SinceBoot(unsafe { sceKernelGetSystemTimeWide() }.max(0) as u64)
+    }
+}
+
+impl TimeSource for RtcClock {
+    fn now(&self) -> Timestamp {
+        let mut time = ScePspDateTime {
+            year: 0,
+            month: 0,
+            day: 0,
+            hour: 0,
+            minutes: 0,
+            seconds: 0,
+            microseconds: 0,
+        };
+
+        unsafe {
+            sceRtcGetCurrentClockLocalTime(&mut time);
+        }
+
+        Timestamp::Local(DateTime {
+            year: time.year,
+            month: time.month,
+            day: time.day,
+            hour: time.hour,
+            minute: time.minutes,
+            second: time.seconds,
+            microsecond: time.microseconds,
+        })
+    }
+}
+
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Timestamp::SinceBoot(micros) => {
+                write!(f, "{}.{:06}", micros / 1_000_000, micros % 1_000_000)
+            }
+            Timestamp::Local(time) => time.fmt(f),
+        }
+    }
+}
+
+impl Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:06}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, self.microsecond
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host;
+    use std::string::ToString;
+
+    #[test]
+    fn since_boot_is_seconds_with_micros() {
+        assert_eq!(Timestamp::SinceBoot(12_000_345).to_string(), "12.000345");
+        assert_eq!(Timestamp::SinceBoot(999).to_string(), "0.000999");
+    }
+
+    #[test]
+    fn local_time_is_iso_like() {
+        let time = DateTime {
+            year: 2024,
+            month: 3,
+            day: 9,
+            hour: 7,
+            minute: 5,
+            second: 1,
+            microsecond: 42,
+        };
+
+        assert_eq!(
+            Timestamp::Local(time).to_string(),
+            "2024-03-09 07:05:01.000042"
+        );
+    }
+
+    #[test]
+    fn rtc_clock_reads_local_time() {
+        let time = DateTime {
+            year: 2008,
+            month: 12,
+            day: 31,
+            hour: 23,
+            minute: 59,
+            second: 58,
+            microsecond: 500_000,
+        };
+        host::set_local_time(time);
+
+        assert_eq!(RtcClock.now(), Timestamp::Local(time));
+    }
+}
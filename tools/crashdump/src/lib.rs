@@ -0,0 +1,144 @@
+//! Rendering of crash dumps written by psp-logger as readable text.
+
+use std::fmt::Write;
+
+use psp_logger::dump::{Dump, DumpError, Section};
+
+/// Render a dump as readable text.
+pub fn render(dump: &Dump) -> Result<String, DumpError> {
+    let header = &dump.header;
+    let mut out = String::new();
+
+    // Writing to a String can't fail.
+    let _ = writeln!(out, "Dump version: {}", dump.version);
+    let _ = writeln!(out, "App:          {}", header.app_name);
+    let _ = writeln!(out, "Build:        {}", header.build_id);
+    let _ = writeln!(
+        out,
+        "Firmware:     {:x}.{:02x}",
+        header.firmware_version >> 24,
+        (header.firmware_version >> 16) & 0xff
+    );
+    let _ = writeln!(out, "Time:         {}", header.time);
+
+    for section in dump.sections() {
+        match section? {
+            Section::Registers(regs) => {
+                let _ = write!(out, "=== registers ===\n{}", regs);
+            }
+            Section::Stack(stack) => {
+                let _ = writeln!(out, "=== stack ===");
+                for (address, word) in stack.words() {
+                    let _ = writeln!(out, "{:#010x}: {:#010x}", address, word);
+                }
+            }
+            Section::LogLines(lines) => {
+                let _ = writeln!(out, "=== recent log lines ===");
+                for line in lines {
+                    let _ = writeln!(out, "{}", line);
+                }
+            }
+            Section::Module(text) => {
+                let _ = writeln!(out, "{}", text);
+            }
+            Section::Unknown { kind, data } => {
+                let _ = writeln!(
+                    out,
+                    "=== unknown section {} ({} bytes) ===",
+                    kind,
+                    data.len()
+                );
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use psp_logger::dump::{DumpHeader, DumpWriter};
+    use psp_logger::{DateTime, RegisterSet, TextSegment};
+
+    fn header() -> DumpHeader<'static> {
+        DumpHeader {
+            app_name: "my_game",
+            build_id: "1.2.3-abcdef",
+            firmware_version: 0x0606_0010,
+            time: DateTime {
+                year: 2024,
+                month: 5,
+                day: 17,
+                hour: 21,
+                minute: 3,
+                second: 9,
+                microsecond: 123_456,
+            },
+        }
+    }
+
+    fn write(f: impl FnOnce(&mut DumpWriter)) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut out = |bytes: &[u8]| data.extend_from_slice(bytes);
+
+        let mut writer = DumpWriter::new(&mut out, &header());
+        f(&mut writer);
+        writer.finish();
+
+        data
+    }
+
+    #[test]
+    fn every_section_is_rendered() {
+        let mut regs = RegisterSet {
+            epc: 0x0880_4a3c,
+            cause: 7 << 2,
+            badvaddr: 4,
+            status: 0x2008_8613,
+            gpr: [0; 32],
+        };
+        regs.gpr[31] = 0x0880_1234;
+        let text = TextSegment {
+            addr: 0x0880_4000,
+            size: 0x1000,
+        };
+
+        let data = write(|writer| {
+            writer.registers(&regs);
+            writer.module(&text);
+            writer.stack(0x09ff_f000, &[1, 0x0880_1234]);
+            writer.log_lines(&|f| {
+                f("one");
+                f("two");
+            });
+        });
+
+        let expected = format!(
+            "Dump version: 1\n\
+             App:          my_game\n\
+             Build:        1.2.3-abcdef\n\
+             Firmware:     6.06\n\
+             Time:         2024-05-17 21:03:09.123456\n\
+             === registers ===\n\
+             {}\
+             module text at 0x08804000, size 0x1000\n\
+             === stack ===\n\
+             0x09fff000: 0x00000001\n\
+             0x09fff004: 0x08801234\n\
+             === recent log lines ===\n\
+             one\n\
+             two\n",
+            regs
+        );
+        assert_eq!(render(&Dump::parse(&data).unwrap()).unwrap(), expected);
+    }
+
+    #[test]
+    fn truncated_sections_are_reported() {
+        let data = write(|writer| writer.stack(0, &[1, 2, 3]));
+        let dump = Dump::parse(&data[..data.len() - 10]).unwrap();
+
+        assert_eq!(render(&dump).unwrap_err(), DumpError::Truncated);
+    }
+}
@@ -0,0 +1,35 @@
+//! Print a crash dump written by psp-logger as readable text.
+//!
+//! Usage: `psp-crashdump <crash.dmp>`
+
+use std::process::ExitCode;
+
+use psp_crashdump::render;
+use psp_logger::dump::Dump;
+
+fn main() -> ExitCode {
+    let Some(path) = std::env::args().nth(1) else {
+        eprintln!("usage: psp-crashdump <crash.dmp>");
+        return ExitCode::FAILURE;
+    };
+
+    let data = match std::fs::read(&path) {
+        Ok(data) => data,
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let result = Dump::parse(&data).and_then(|dump| render(&dump));
+    match result {
+        Ok(text) => {
+            print!("{}", text);
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            ExitCode::FAILURE
+        }
+    }
+}
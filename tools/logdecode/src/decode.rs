@@ -0,0 +1,186 @@
+//! Decoding captured output holding both text lines and binary records.
+
+use std::borrow::Cow;
+use std::fmt::Write;
+
+use psp_logger::binary::{Frame, FRAME_START};
+use psp_logger::TextSegment;
+
+use crate::formats::Formats;
+use crate::render::render;
+
+/// Part of the captured output.
+enum Piece<'a> {
+    /// A line of text, or what's left of one.
+    Text(Cow<'a, str>),
+    Frame(Frame<'a>),
+
+    /// A frame cut short by the end of the capture.
+    Truncated,
+}
+
+/// Split `input` into text and frames.
+fn split(input: &[u8]) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut rest = input;
+
+    while let Some(&first) = rest.first() {
+        if first == FRAME_START {
+            match Frame::parse(rest) {
+                Ok((frame, len)) => {
+                    pieces.push(Piece::Frame(frame));
+                    rest = &rest[len..];
+                }
+                Err(_) => {
+                    pieces.push(Piece::Truncated);
+                    break;
+                }
+            }
+            continue;
+        }
+
+        let len = match rest
+            .iter()
+            .position(|&byte| byte == b'\n' || byte == FRAME_START)
+        {
+            Some(index) if rest[index] == b'\n' => index + 1,
+            Some(index) => index,
+            None => rest.len(),
+        };
+        pieces.push(Piece::Text(String::from_utf8_lossy(&rest[..len])));
+        rest = &rest[len..];
+    }
+
+    pieces
+}
+
+/// Turn the binary records in `input` back into text, using the format strings in `formats`.
+///
+/// Each record is written on its own line as `LEVEL target: message`, and text written by other
+/// records is copied as it is. Record ids are moved from where the module was loaded to where it
+/// was linked using the text segment from the line the logger writes at startup. A capture can
+/// hold several runs of a program, so each record uses the most recent of those lines before it,
+/// or the first one if there are none before it. Without any such lines, ids are used as they
+/// are.
+pub fn decode(input: &[u8], formats: &Formats) -> String {
+    let pieces = split(input);
+    let mut segment = pieces.iter().find_map(|piece| match piece {
+        Piece::Text(text) => TextSegment::from_log_line(text),
+        _ => None,
+    });
+    let mut out = String::with_capacity(input.len() * 2);
+
+    for piece in &pieces {
+        match piece {
+            Piece::Text(text) => {
+                if let Some(found) = TextSegment::from_log_line(text) {
+                    segment = Some(found);
+                }
+                out.push_str(text);
+            }
+            Piece::Frame(frame) => {
+                let addr = match segment {
+                    Some(segment) => frame
+                        .id
+                        .wrapping_sub(segment.addr)
+                        .wrapping_add(formats.link_base()),
+                    None => frame.id,
+                };
+
+                // Writing to a String can't fail.
+                let _ = match formats.entry(addr) {
+                    Some(entry) => {
+                        let args: Vec<_> = frame.args().map_while(Result::ok).collect();
+                        writeln!(
+                            out,
+                            "{} {}: {}",
+                            entry.level,
+                            entry.target,
+                            render(entry.format, &args)
+                        )
+                    }
+                    None => writeln!(out, "<unknown binary record {:#010x}>", frame.id),
+                };
+            }
+            Piece::Truncated => out.push_str("<truncated binary record>\n"),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use psp_logger::binary::FrameWriter;
+
+    /// A format entry, laid out as in the ELF section.
+    fn entry(level: u8, target: &str, format: &str) -> Vec<u8> {
+        let mut entry = vec![level];
+        for s in [target, format] {
+            entry.extend_from_slice(&(s.len() as u16).to_le_bytes());
+            entry.extend_from_slice(s.as_bytes());
+        }
+        entry
+    }
+
+    /// Two entries, linked at 0x100 and 0x100 plus the length of the first.
+    fn formats() -> (Formats, u32) {
+        let mut data = entry(3, "game", "spawned {} at {:#x}");
+        let second = 0x100 + data.len() as u32;
+        data.extend(entry(1, "game::io", "read failed"));
+
+        (Formats::from_section(0x100, data, 0), second)
+    }
+
+    fn frame(id: u32, args: &[&dyn psp_logger::binary::BinaryArg]) -> Vec<u8> {
+        let mut writer = FrameWriter::new(id);
+        for arg in args {
+            writer.arg(*arg);
+        }
+        writer.finish().to_vec()
+    }
+
+    #[test]
+    fn frames_are_decoded_between_text_lines() {
+        let (formats, second) = formats();
+
+        let mut input = b"module text at 0x08804000, size 0x1000\n".to_vec();
+        input.extend(frame(0x0880_4100, &[&"bat", &0x40u32]));
+        input.extend(b"plain text\n");
+        input.extend(frame(0x0880_4000 + second, &[]));
+
+        assert_eq!(
+            decode(&input, &formats),
+            "module text at 0x08804000, size 0x1000\n\
+             INFO game: spawned bat at 0x40\n\
+             plain text\n\
+             ERROR game::io: read failed\n"
+        );
+    }
+
+    #[test]
+    fn ids_are_used_as_they_are_without_a_text_segment() {
+        let (formats, _) = formats();
+
+        assert_eq!(
+            decode(&frame(0x100, &[&"bat", &1u8]), &formats),
+            "INFO game: spawned bat at 0x1\n"
+        );
+    }
+
+    #[test]
+    fn unknown_and_truncated_frames_are_marked() {
+        let (formats, _) = formats();
+
+        let mut input = frame(0x0900_0000, &[]);
+        let truncated = frame(0x100, &[&"bat", &1u8]);
+        input.extend(&truncated[..truncated.len() - 1]);
+
+        assert_eq!(
+            decode(&input, &formats),
+            "<unknown binary record 0x09000000>\n\
+             <truncated binary record>\n"
+        );
+    }
+}
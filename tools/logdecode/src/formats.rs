@@ -0,0 +1,62 @@
+//! Reading the format strings of binary records from an unstripped ELF or PRX.
+
+use std::error::Error;
+
+use object::{Object, ObjectSection, ObjectSegment};
+use psp_logger::binary::{FormatEntry, FORMAT_SECTION};
+
+/// The contents of the format string section, looked up by the addresses it was linked at.
+pub struct Formats {
+    addr: u32,
+    data: Vec<u8>,
+    link_base: u32,
+}
+
+impl Formats {
+    /// Read the format string section from an ELF.
+    pub fn new(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let file = object::File::parse(data)?;
+
+        let section = file.section_by_name(FORMAT_SECTION).ok_or_else(|| {
+            format!(
+                "no {} section, so no binary records were logged",
+                FORMAT_SECTION
+            )
+        })?;
+
+        // PRXs are linked at 0 and relocated when loaded, with their first segment at the
+        // address the logger reports as the start of the text segment.
+        let link_base = file
+            .segments()
+            .map(|segment| segment.address())
+            .min()
+            .unwrap_or(0) as u32;
+
+        Ok(Self::from_section(
+            section.address() as u32,
+            section.data()?.to_vec(),
+            link_base,
+        ))
+    }
+
+    /// Use the contents of a format string section linked at `addr`.
+    pub fn from_section(addr: u32, data: Vec<u8>, link_base: u32) -> Self {
+        Formats {
+            addr,
+            data,
+            link_base,
+        }
+    }
+
+    /// Address the start of the text segment was linked at.
+    pub fn link_base(&self) -> u32 {
+        self.link_base
+    }
+
+    /// The format entry linked at `addr`, if there is a valid one.
+    pub fn entry(&self, addr: u32) -> Option<FormatEntry<'_>> {
+        let offset = addr.checked_sub(self.addr)? as usize;
+
+        FormatEntry::parse(self.data.get(offset..)?).ok()
+    }
+}
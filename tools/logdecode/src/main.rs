@@ -0,0 +1,38 @@
+//! Turn the binary records in output captured from psp-logger back into text.
+//!
+//! Usage: `psp-logdecode <game.elf|game.prx> <capture.log>`
+//!
+//! The capture can be a log file from the memory stick, or the output of PSPLink saved on the
+//! host. The ELF or PRX has to be the unstripped build of the program that wrote it.
+
+mod decode;
+mod formats;
+mod render;
+
+use std::process::ExitCode;
+
+use formats::Formats;
+
+fn main() -> ExitCode {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let [elf_path, input_path] = &args[..] else {
+        eprintln!("usage: psp-logdecode <game.elf|game.prx> <capture.log>");
+        return ExitCode::FAILURE;
+    };
+
+    let read = |path: &str| std::fs::read(path).map_err(|error| eprintln!("{}: {}", path, error));
+    let (Ok(elf), Ok(input)) = (read(elf_path), read(input_path)) else {
+        return ExitCode::FAILURE;
+    };
+
+    let formats = match Formats::new(&elf) {
+        Ok(formats) => formats,
+        Err(error) => {
+            eprintln!("{}: {}", elf_path, error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    print!("{}", decode::decode(&input, &formats));
+    ExitCode::SUCCESS
+}
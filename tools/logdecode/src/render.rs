@@ -0,0 +1,317 @@
+//! Substituting the arguments of a binary record into its format string.
+
+use psp_logger::binary::ArgValue;
+
+/// Written in place of a placeholder that can't be filled in.
+const MISSING: &str = "{?}";
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// Options from a placeholder, e.g. `{:>#010x}`.
+struct Spec<'a> {
+    fill: char,
+    align: Option<Align>,
+    plus: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    kind: &'a str,
+}
+
+/// Substitute `args` into `format`, as `format!` would have.
+///
+/// Implicit and positional placeholders are supported, along with fill, alignment, `+`, `#`,
+/// `0`, width, precision and the `?`, `x`, `X`, `b`, `o`, `e` and `E` types. Placeholders
+/// naming a variable or taking their width from an argument, and those without an argument
+/// because it didn't fit in the frame, are written as `{?}`.
+pub fn render(format: &str, args: &[ArgValue]) -> String {
+    let mut out = String::with_capacity(format.len());
+    let mut next = 0;
+    let mut rest = format;
+
+    while let Some(index) = rest.find(['{', '}']) {
+        out.push_str(&rest[..index]);
+        let brace = &rest[index..index + 1];
+        rest = &rest[index + 1..];
+
+        // `{{` and `}}` are escaped braces. A lone `}` wouldn't have compiled.
+        if let Some(escaped) = rest.strip_prefix(brace) {
+            out.push_str(brace);
+            rest = escaped;
+            continue;
+        }
+        if brace == "}" {
+            out.push_str(brace);
+            continue;
+        }
+
+        let Some(end) = rest.find('}') else {
+            out.push_str(brace);
+            break;
+        };
+        let placeholder = &rest[..end];
+        rest = &rest[end + 1..];
+
+        let (position, spec) = placeholder.split_once(':').unwrap_or((placeholder, ""));
+        let arg = if position.is_empty() {
+            next += 1;
+            args.get(next - 1)
+        } else {
+            position
+                .parse()
+                .ok()
+                .and_then(|index: usize| args.get(index))
+        };
+
+        match (arg, parse_spec(spec)) {
+            (Some(arg), Some(spec)) => out.push_str(&format_arg(arg, &spec)),
+            _ => out.push_str(MISSING),
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Parse the part of a placeholder after the `:`, or `None` if it isn't supported.
+fn parse_spec(spec: &str) -> Option<Spec<'_>> {
+    let align_of = |c: char| match c {
+        '<' => Some(Align::Left),
+        '^' => Some(Align::Center),
+        '>' => Some(Align::Right),
+        _ => None,
+    };
+
+    let mut parsed = Spec {
+        fill: ' ',
+        align: None,
+        plus: false,
+        alternate: false,
+        zero: false,
+        width: 0,
+        precision: None,
+        kind: "",
+    };
+
+    let mut chars = spec.chars();
+    let mut rest = spec;
+    match (chars.next(), chars.next().and_then(align_of)) {
+        (Some(fill), Some(align)) => {
+            parsed.fill = fill;
+            parsed.align = Some(align);
+            rest = chars.as_str();
+        }
+        (Some(first), _) if align_of(first).is_some() => {
+            parsed.align = align_of(first);
+            rest = &rest[1..];
+        }
+        _ => {}
+    }
+
+    if let Some(after) = rest.strip_prefix('+') {
+        parsed.plus = true;
+        rest = after;
+    }
+    if let Some(after) = rest.strip_prefix('#') {
+        parsed.alternate = true;
+        rest = after;
+    }
+    if let Some(after) = rest.strip_prefix('0') {
+        parsed.zero = true;
+        rest = after;
+    }
+
+    let (width, after) = split_number(rest);
+    parsed.width = width.unwrap_or(0);
+    rest = after;
+
+    if let Some(after) = rest.strip_prefix('.') {
+        let (precision, after) = split_number(after);
+        parsed.precision = Some(precision?);
+        rest = after;
+    }
+
+    match rest {
+        "" | "?" | "x" | "X" | "b" | "o" | "e" | "E" => {
+            parsed.kind = rest;
+            Some(parsed)
+        }
+        _ => None,
+    }
+}
+
+/// Split the decimal number at the start of `s` from the rest of it.
+fn split_number(s: &str) -> (Option<usize>, &str) {
+    let len = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+
+    (s[..len].parse().ok(), &s[len..])
+}
+
+/// Format a single argument with the options from its placeholder.
+fn format_arg(arg: &ArgValue, spec: &Spec) -> String {
+    macro_rules! integer {
+        ($value:expr) => {
+            match spec.kind {
+                "x" => format!("{:x}", $value),
+                "X" => format!("{:X}", $value),
+                "b" => format!("{:b}", $value),
+                "o" => format!("{:o}", $value),
+                "e" => format!("{:e}", $value),
+                "E" => format!("{:E}", $value),
+                _ => format!("{}", $value),
+            }
+        };
+    }
+
+    macro_rules! float {
+        ($value:expr) => {
+            match (spec.kind, spec.precision) {
+                ("e", Some(precision)) => format!("{:.*e}", precision, $value),
+                ("E", Some(precision)) => format!("{:.*E}", precision, $value),
+                ("e", None) => format!("{:e}", $value),
+                ("E", None) => format!("{:E}", $value),
+                (_, Some(precision)) => format!("{:.*}", precision, $value),
+                ("?", None) => format!("{:?}", $value),
+                _ => format!("{}", $value),
+            }
+        };
+    }
+
+    let (text, numeric) = match *arg {
+        ArgValue::U8(value) => (integer!(value), true),
+        ArgValue::U16(value) => (integer!(value), true),
+        ArgValue::U32(value) => (integer!(value), true),
+        ArgValue::U64(value) => (integer!(value), true),
+        ArgValue::I8(value) => (integer!(value), true),
+        ArgValue::I16(value) => (integer!(value), true),
+        ArgValue::I32(value) => (integer!(value), true),
+        ArgValue::I64(value) => (integer!(value), true),
+        ArgValue::F32(value) => (float!(value), true),
+        ArgValue::F64(value) => (float!(value), true),
+        ArgValue::Bool(value) => (value.to_string(), false),
+        ArgValue::Char(value) if spec.kind == "?" => (format!("{:?}", value), false),
+        ArgValue::Char(value) => (value.to_string(), false),
+        ArgValue::Str(value) if spec.kind == "?" => (format!("{:?}", value), false),
+        ArgValue::Str(value) => match spec.precision {
+            Some(precision) => (value.chars().take(precision).collect(), false),
+            None => (value.to_string(), false),
+        },
+    };
+
+    if !numeric {
+        return pad(&text, spec, Align::Left);
+    }
+
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None if spec.plus => ("+", text.as_str()),
+        None => ("", text.as_str()),
+    };
+    let prefix = match (spec.alternate, spec.kind) {
+        (true, "x" | "X") => "0x",
+        (true, "b") => "0b",
+        (true, "o") => "0o",
+        _ => "",
+    };
+
+    if spec.zero {
+        let len = sign.len() + prefix.len() + digits.chars().count();
+        let zeros = "0".repeat(spec.width.saturating_sub(len));
+        return format!("{}{}{}{}", sign, prefix, zeros, digits);
+    }
+
+    pad(&format!("{}{}{}", sign, prefix, digits), spec, Align::Right)
+}
+
+/// Pad `text` to the placeholder's width, aligning it with `default` unless it says otherwise.
+fn pad(text: &str, spec: &Spec, default: Align) -> String {
+    let padding = spec.width.saturating_sub(text.chars().count());
+    let (before, after) = match spec.align.unwrap_or(default) {
+        Align::Left => (0, padding),
+        Align::Center => (padding / 2, padding - padding / 2),
+        Align::Right => (padding, 0),
+    };
+
+    let fill = |count| std::iter::repeat_n(spec.fill, count).collect::<String>();
+    format!("{}{}{}", fill(before), text, fill(after))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments_are_substituted_in_order() {
+        assert_eq!(
+            render(
+                "{} hit {} for {} ({:?})",
+                &[
+                    ArgValue::Str("player"),
+                    ArgValue::Str("bat"),
+                    ArgValue::I32(-12),
+                    ArgValue::Bool(true)
+                ]
+            ),
+            "player hit bat for -12 (true)"
+        );
+        assert_eq!(
+            render("{1} {0} {}", &[ArgValue::U8(1), ArgValue::U8(2)]),
+            "2 1 1"
+        );
+    }
+
+    #[test]
+    fn format_options_match_std() {
+        let cases: &[(&str, ArgValue, String)] = &[
+            (
+                "{:#010x}",
+                ArgValue::U32(0xbeef),
+                format!("{:#010x}", 0xbeefu32),
+            ),
+            ("{:X}", ArgValue::I8(-1), format!("{:X}", -1i8)),
+            ("{:#b}", ArgValue::U8(5), format!("{:#b}", 5u8)),
+            ("{:+05}", ArgValue::I16(42), format!("{:+05}", 42i16)),
+            ("{:05}", ArgValue::I64(-42), format!("{:05}", -42i64)),
+            ("{:>6}", ArgValue::U16(7), format!("{:>6}", 7u16)),
+            ("{:6}", ArgValue::U16(7), format!("{:6}", 7u16)),
+            ("{:*^7}", ArgValue::Str("mid"), format!("{:*^7}", "mid")),
+            ("{:<4}|", ArgValue::Char('c'), format!("{:<4}|", 'c')),
+            ("{:.2}", ArgValue::F32(1.005), format!("{:.2}", 1.005f32)),
+            ("{:8.3}", ArgValue::F64(-2.5), format!("{:8.3}", -2.5f64)),
+            ("{:?}", ArgValue::F64(1.0), format!("{:?}", 1.0f64)),
+            ("{:e}", ArgValue::F32(1500.0), format!("{:e}", 1500.0f32)),
+            ("{:?}", ArgValue::Str("a\"b"), format!("{:?}", "a\"b")),
+            (
+                "{:.3}",
+                ArgValue::Str("truncate"),
+                format!("{:.3}", "truncate"),
+            ),
+        ];
+
+        for (format, arg, expected) in cases {
+            assert_eq!(&render(format, &[*arg]), expected, "{}", format);
+        }
+    }
+
+    #[test]
+    fn braces_can_be_escaped() {
+        assert_eq!(render("{{{}}}", &[ArgValue::U8(1)]), "{1}");
+    }
+
+    #[test]
+    fn unfillable_placeholders_are_marked() {
+        assert_eq!(
+            render(
+                "{} {} {name} {:w$} {:x?}",
+                &[ArgValue::U8(1), ArgValue::U8(2), ArgValue::U8(3)]
+            ),
+            "1 2 {?} {?} {?}"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! Looking up code addresses in an unstripped ELF or PRX.
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::rc::Rc;
+
+use gimli::{EndianRcSlice, RunTimeEndian};
+use object::{Object, ObjectSection, ObjectSegment, ObjectSymbol, SymbolKind};
+
+/// A function in the symbol table.
+struct Function {
+    addr: u32,
+    size: u32,
+    name: String,
+}
+
+/// Symbols and line information from an ELF, looked up by the addresses it was linked at.
+pub struct Symbolizer {
+    functions: Vec<Function>,
+    lines: addr2line::Context<EndianRcSlice<RunTimeEndian>>,
+    link_base: u32,
+}
+
+impl Symbolizer {
+    /// Read the symbol table and any DWARF line information from an ELF.
+    pub fn new(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let file = object::File::parse(data)?;
+
+        let mut functions: Vec<_> = file
+            .symbols()
+            .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.is_definition())
+            .filter_map(|symbol| {
+                Some(Function {
+                    addr: symbol.address() as u32,
+                    size: symbol.size() as u32,
+                    name: format!("{:#}", rustc_demangle::demangle(symbol.name().ok()?)),
+                })
+            })
+            .collect();
+        functions.sort_by_key(|function| function.addr);
+
+        let endian = if file.is_little_endian() {
+            RunTimeEndian::Little
+        } else {
+            RunTimeEndian::Big
+        };
+        let dwarf = gimli::Dwarf::load(|id| -> Result<_, object::Error> {
+            let data = match file.section_by_name(id.name()) {
+                Some(section) => section.uncompressed_data()?,
+                None => Cow::Borrowed(&[][..]),
+            };
+            Ok(EndianRcSlice::new(Rc::from(&*data), endian))
+        })?;
+
+        // PRXs are linked at 0 and relocated when loaded, with their first segment at the
+        // address the logger reports as the start of the text segment.
+        let link_base = file
+            .segments()
+            .map(|segment| segment.address())
+            .min()
+            .unwrap_or(0) as u32;
+
+        Ok(Symbolizer {
+            functions,
+            lines: addr2line::Context::from_dwarf(dwarf)?,
+            link_base,
+        })
+    }
+
+    /// Address the start of the text segment was linked at.
+    pub fn link_base(&self) -> u32 {
+        self.link_base
+    }
+
+    /// Describe a link time address as `function+offset (file:line)`.
+    ///
+    /// Returns `None` if the address isn't inside a function. The location is left out if the
+    /// ELF has no line information for it.
+    pub fn lookup(&self, addr: u32) -> Option<String> {
+        let index = self
+            .functions
+            .partition_point(|function| function.addr <= addr)
+            .checked_sub(1)?;
+        let function = &self.functions[index];
+
+        if function.size != 0 && addr - function.addr >= function.size {
+            return None;
+        }
+
+        let mut symbol = format!("{}+{:#x}", function.name, addr - function.addr);
+
+        if let Ok(Some(location)) = self.lines.find_location(addr as u64) {
+            if let (Some(file), Some(line)) = (location.file, location.line) {
+                symbol.push_str(&format!(" ({}:{})", file, line));
+            }
+        }
+
+        Some(symbol)
+    }
+}
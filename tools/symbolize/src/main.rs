@@ -0,0 +1,54 @@
+//! Replace code addresses in a psp-logger log or crash dump with `function+offset (file:line)`.
+//!
+//! Usage: `psp-symbolize <game.elf|game.prx> <log.txt|crash.dmp>`
+//!
+//! The ELF or PRX has to be the unstripped build of the program that wrote the log or dump.
+
+mod elf;
+mod text;
+
+use std::process::ExitCode;
+
+use psp_logger::dump::{Dump, DUMP_MAGIC};
+
+use elf::Symbolizer;
+
+fn main() -> ExitCode {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let [elf_path, input_path] = &args[..] else {
+        eprintln!("usage: psp-symbolize <game.elf|game.prx> <log.txt|crash.dmp>");
+        return ExitCode::FAILURE;
+    };
+
+    let read = |path: &str| std::fs::read(path).map_err(|error| eprintln!("{}: {}", path, error));
+    let (Ok(elf), Ok(input)) = (read(elf_path), read(input_path)) else {
+        return ExitCode::FAILURE;
+    };
+
+    let symbolizer = match Symbolizer::new(&elf) {
+        Ok(symbolizer) => symbolizer,
+        Err(error) => {
+            eprintln!("{}: {}", elf_path, error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let input = if input.starts_with(&DUMP_MAGIC) {
+        match Dump::parse(&input).and_then(|dump| psp_crashdump::render(&dump)) {
+            Ok(text) => text,
+            Err(error) => {
+                eprintln!("{}: {}", input_path, error);
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        String::from_utf8_lossy(&input).into_owned()
+    };
+
+    print!(
+        "{}",
+        text::symbolize(&input, symbolizer.link_base(), |addr| symbolizer
+            .lookup(addr))
+    );
+    ExitCode::SUCCESS
+}
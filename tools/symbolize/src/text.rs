@@ -0,0 +1,116 @@
+//! Replacing code addresses in log text.
+
+use psp_logger::TextSegment;
+
+/// Replace each address in `text` with the result of `lookup`.
+///
+/// Addresses are written by the logger as `0x` followed by eight hex digits. They are moved from
+/// where the module was loaded to where it was linked, at `link_base`, using the text segment
+/// from the line the logger writes at startup. A log can hold several runs of a program, so each
+/// line uses the most recent of those lines before it, or the first one if there are none
+/// before it. Addresses outside the text segment, and those `lookup` returns `None` for, are
+/// left alone. Without any such lines, addresses are looked up as they are.
+pub fn symbolize(text: &str, link_base: u32, lookup: impl Fn(u32) -> Option<String>) -> String {
+    let mut segment = text.lines().find_map(TextSegment::from_log_line);
+    let mut out = String::with_capacity(text.len());
+
+    for line in text.split_inclusive('\n') {
+        if let Some(found) = TextSegment::from_log_line(line) {
+            segment = Some(found);
+            out.push_str(line);
+            continue;
+        }
+
+        replace_addresses(line, &mut out, |addr| match segment {
+            Some(segment) if segment.contains(addr) => lookup(addr - segment.addr + link_base),
+            Some(_) => None,
+            None => lookup(addr),
+        });
+    }
+
+    out
+}
+
+/// Copy `line` to `out`, replacing the addresses that `lookup` knows about.
+fn replace_addresses(line: &str, out: &mut String, lookup: impl Fn(u32) -> Option<String>) {
+    let bytes = line.as_bytes();
+    let is_word = |index: usize| bytes.get(index).is_some_and(u8::is_ascii_alphanumeric);
+
+    let mut copied = 0;
+    let mut index = 0;
+    while index + 10 <= bytes.len() {
+        let is_address = bytes[index..].starts_with(b"0x")
+            && bytes[index + 2..index + 10]
+                .iter()
+                .all(u8::is_ascii_hexdigit)
+            && (index == 0 || !is_word(index - 1))
+            && !is_word(index + 10);
+
+        if !is_address {
+            index += 1;
+            continue;
+        }
+
+        // The address is ASCII, so these are character boundaries.
+        let addr = u32::from_str_radix(&line[index + 2..index + 10], 16).unwrap_or_default();
+        if let Some(symbol) = lookup(addr) {
+            out.push_str(&line[copied..index]);
+            out.push_str(&symbol);
+            copied = index + 10;
+        }
+
+        index += 10;
+    }
+
+    out.push_str(&line[copied..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pretends every address from 0x100 to 0x200 is in `f`.
+    fn lookup(addr: u32) -> Option<String> {
+        (0x100..0x200)
+            .contains(&addr)
+            .then(|| format!("f+{:#x}", addr - 0x100))
+    }
+
+    #[test]
+    fn addresses_are_relocated() {
+        let text = "module text at 0x08804000, size 0x1000\n\
+                    ra=0x08804104 sp=0x09fff000 0x0880410c0\n";
+
+        assert_eq!(
+            symbolize(text, 0, lookup),
+            "module text at 0x08804000, size 0x1000\n\
+             ra=f+0x4 sp=0x09fff000 0x0880410c0\n"
+        );
+    }
+
+    #[test]
+    fn each_run_uses_its_own_text_segment() {
+        let text = "epc 0x08804110\n\
+                    module text at 0x08804000, size 0x1000\n\
+                    epc 0x08804120\n\
+                    module text at 0x08900000, size 0x1000\n\
+                    epc 0x08804120 0x08900130\n";
+
+        assert_eq!(
+            symbolize(text, 0x80, lookup),
+            "epc f+0x90\n\
+             module text at 0x08804000, size 0x1000\n\
+             epc f+0xa0\n\
+             module text at 0x08900000, size 0x1000\n\
+             epc 0x08804120 f+0xb0\n"
+        );
+    }
+
+    #[test]
+    fn addresses_are_used_as_they_are_without_a_text_segment() {
+        assert_eq!(
+            symbolize("at 0x00000150, not x0x00000150", 0, lookup),
+            "at f+0x50, not x0x00000150"
+        );
+    }
+}